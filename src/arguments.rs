@@ -0,0 +1,85 @@
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: editore [options] [file[:line] | +line file]...
+
+Opens each file in a buffer, at the given line if there is one. With no
+file, input piped to editore is opened instead.
+
+Options:
+  -R             Open the files read-only
+  -h, --help     Show this help and exit
+  -V, --version  Show the version and exit
+  --             Treat everything after as file names
+";
+
+/// What to do about the files given on the command line
+#[derive(Default)]
+pub struct Arguments {
+    pub read_only: bool,
+    /// The files to open, each with the line to open it at when given as
+    /// `file:line` or as `+line` before the file
+    pub files: Vec<(String, Option<usize>)>,
+}
+
+/// What the command line asks for
+pub enum Invocation {
+    Edit(Arguments),
+    Help,
+    Version,
+}
+
+impl Invocation {
+    /// Reads `args`, which start with the program name like `env::args`.
+    /// Options are recognized until `--`, so files with names like options
+    /// can still be opened.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut arguments = Arguments::default();
+        let mut pending_line = None;
+        let mut options_ended = false;
+        for arg in args.iter().skip(1) {
+            if !options_ended {
+                match arg.as_str() {
+                    "--" => {
+                        options_ended = true;
+                        continue;
+                    }
+                    "-h" | "--help" => return Ok(Self::Help),
+                    "-V" | "--version" => return Ok(Self::Version),
+                    "-R" => {
+                        arguments.read_only = true;
+                        continue;
+                    }
+                    // A lone `-` is taken as a file name
+                    option if option.len() > 1 && option.starts_with('-') => {
+                        return Err(format!("Unknown option: {option}"));
+                    }
+                    _ => (),
+                }
+                if let Some(number) = arg.strip_prefix('+').and_then(parse_line_number) {
+                    pending_line = Some(number);
+                    continue;
+                }
+            }
+            let line = pending_line.take();
+            // A file whose name merely ends in `:digits` opens as it is
+            let split = arg
+                .rsplit_once(':')
+                .filter(|(name, _)| !name.is_empty() && !Path::new(arg).exists())
+                .and_then(|(name, number)| Some((name.to_string(), parse_line_number(number)?)));
+            arguments.files.push(match split {
+                Some((file_name, number)) => (file_name, Some(number)),
+                None => (arg.clone(), line),
+            });
+        }
+        Ok(Self::Edit(arguments))
+    }
+}
+
+/// Reads a line number, too big ones meaning the last line
+fn parse_line_number(number: &str) -> Option<usize> {
+    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some(number.parse().unwrap_or(usize::MAX))
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::{SyntaxDefinition, SyntaxSet};
+
+use crate::config;
+
+/// The default syntaxes along with the `.sublime-syntax` files found in
+/// `~/.config/editore/syntaxes`, and a warning for each file that couldn't
+/// be loaded
+pub fn load_syntaxes() -> (SyntaxSet, Vec<String>) {
+    let defaults = SyntaxSet::load_defaults_newlines();
+    let mut warnings = Vec::new();
+    let paths = user_files("syntaxes", "sublime-syntax");
+    if paths.is_empty() {
+        return (defaults, warnings);
+    }
+
+    let mut builder = defaults.into_builder();
+    for path in paths {
+        let fallback_name = path.file_stem().and_then(|stem| stem.to_str());
+        let definition = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| {
+                SyntaxDefinition::load_from_str(&contents, true, fallback_name)
+                    .map_err(|error| error.to_string())
+            });
+        match definition {
+            Ok(definition) => builder.add(definition),
+            Err(error) => warnings.push(format!("Skipped {}: {error}", path.display())),
+        }
+    }
+    (builder.build(), warnings)
+}
+
+/// The default themes along with the `.tmTheme` files found in
+/// `~/.config/editore/themes`, named after the file. A warning is returned
+/// for each file that couldn't be loaded.
+pub fn load_themes() -> (ThemeSet, Vec<String>) {
+    let mut themes = ThemeSet::load_defaults();
+    let mut warnings = Vec::new();
+    for path in user_files("themes", "tmTheme") {
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        match ThemeSet::get_theme(&path) {
+            Ok(theme) => {
+                themes.themes.insert(name.to_string(), theme);
+            }
+            Err(error) => warnings.push(format!("Skipped {}: {error}", path.display())),
+        }
+    }
+    (themes, warnings)
+}
+
+/// The files with the given extension in a subdirectory of the config
+/// directory, sorted so they load in a predictable order
+fn user_files(directory: &str, extension: &str) -> Vec<PathBuf> {
+    let Some(directory) = config::config_dir().map(|dir| dir.join(directory)) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| has_extension(path, extension))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == extension)
+}
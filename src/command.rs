@@ -0,0 +1,83 @@
+/// A command typed at the `:` prompt
+pub enum Command {
+    /// `:w`, optionally saving under a new name
+    Write(Option<String>),
+    /// `:q`, or `:q!` to quit without checking for unsaved changes
+    Quit { force: bool },
+    /// `:wq`
+    WriteQuit,
+    /// `:e path`
+    Edit(String),
+    /// `:e!`, reading the file again and dropping unsaved changes
+    Reload,
+    /// `:view`, switching to read-only mode, optionally opening a file
+    View(Option<String>),
+    /// `:set option`, `:set nooption` disabling it
+    Set { option: String, enabled: bool },
+    /// `:theme name`
+    Theme(String),
+    /// `:count`, the lines, words and characters of the document or the
+    /// selection
+    Count,
+    /// `:conflict next`, `prev`, `ours` or `theirs`
+    Conflict(ConflictAction),
+    /// `:recent`, picking a recently opened file to reopen
+    Recent,
+    /// `:new`, starting an empty unnamed buffer
+    New,
+}
+
+/// What `:conflict` does about the merge conflicts left in a document
+pub enum ConflictAction {
+    Next,
+    Previous,
+    /// Keep our side of the conflict at the cursor
+    Ours,
+    /// Keep their side of the conflict at the cursor
+    Theirs,
+}
+
+impl Command {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim().to_string())),
+            None => (input, None),
+        };
+
+        match (name, argument) {
+            ("w" | "write", file_name) => Ok(Self::Write(file_name)),
+            ("q" | "quit", None) => Ok(Self::Quit { force: false }),
+            ("q!" | "quit!", None) => Ok(Self::Quit { force: true }),
+            ("wq" | "x", None) => Ok(Self::WriteQuit),
+            ("e" | "edit", Some(file_name)) => Ok(Self::Edit(file_name)),
+            ("e!" | "edit!", None) => Ok(Self::Reload),
+            ("view", file_name) => Ok(Self::View(file_name)),
+            ("set", Some(option)) => Ok(match option.strip_prefix("no") {
+                Some(option) => Self::Set {
+                    option: option.to_string(),
+                    enabled: false,
+                },
+                None => Self::Set {
+                    option,
+                    enabled: true,
+                },
+            }),
+            ("theme", Some(theme)) => Ok(Self::Theme(theme)),
+            ("count" | "wc", None) => Ok(Self::Count),
+            ("recent", None) => Ok(Self::Recent),
+            ("new" | "enew", None) => Ok(Self::New),
+            ("conflict", Some(action)) => match action.as_str() {
+                "next" | "n" => Ok(Self::Conflict(ConflictAction::Next)),
+                "prev" | "previous" | "p" => Ok(Self::Conflict(ConflictAction::Previous)),
+                "ours" => Ok(Self::Conflict(ConflictAction::Ours)),
+                "theirs" => Ok(Self::Conflict(ConflictAction::Theirs)),
+                _ => Err(format!("Unknown conflict action: {action}")),
+            },
+            ("e" | "edit" | "set" | "theme" | "conflict", None) => {
+                Err(format!("Missing argument: {name}"))
+            }
+            _ => Err(format!("Unknown command: {input}")),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// A color written as `#rrggbb` in the config file
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid color {value:?}, expected #rrggbb");
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .ok_or_else(invalid)?;
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| invalid());
+        Ok(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    pub theme: String,
+    pub tab_stop: usize,
+    pub insert_spaces: bool,
+    pub show_line_numbers: bool,
+    /// Tint the background of the line the cursor is on
+    pub highlight_current_line: bool,
+    pub quit_times: u8,
+    /// Seconds a message stays in the message bar, errors staying until the
+    /// next key instead
+    pub message_timeout: u64,
+    /// Lines of context to keep visible above and below the cursor
+    pub scrolloff: usize,
+    /// Columns of context to keep visible left and right of the cursor
+    pub sidescrolloff: usize,
+    /// Strip whitespace from the end of every line when saving
+    pub trim_trailing_whitespace: bool,
+    /// Drop blank lines at the end of the file when saving, so it ends with
+    /// exactly one newline. When off, the file keeps ending the way it did.
+    pub final_newline: bool,
+    /// Insert the closing bracket or quote when typing an opening one
+    pub auto_close_brackets: bool,
+    /// Show the whole document shrunk down in a column on the right
+    pub show_minimap: bool,
+    /// Start the line after a comment or a Markdown list item with the same
+    /// comment marker or bullet
+    pub continue_comments: bool,
+    /// Also put cut and copied text on the system clipboard through the
+    /// terminal, which works over SSH but isn't supported by every terminal
+    pub osc52_clipboard: bool,
+    /// Drawn on the lines past the end of the document, an empty string
+    /// leaving them blank
+    pub filler: String,
+    /// Left at the start of a line goes to the end of the previous one,
+    /// and Right at the end of a line to the start of the next
+    pub wrap_cursor: bool,
+    /// Put a warning background behind trailing whitespace and indentation
+    /// mixing tabs and spaces
+    pub show_whitespace_errors: bool,
+    pub status_bg_color: Rgb,
+    pub status_fg_color: Rgb,
+    /// Layout of the status bar, with `{file}`, `{lines}`, `{line}`, `{col}`,
+    /// `{ft}`, `{encoding}`, `{mod}`, `{ro}`, `{ovr}`, `{buffer}` and `{undo}`
+    /// replaced by what they name. What follows `{>}` is aligned right.
+    pub status_format: Option<String>,
+    /// Keys bound to other actions than by default, by action name, like
+    /// `save = "Ctrl-X"`, which the Ctrl-H help lists
+    pub keys: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: String::from("base16-mocha.dark"),
+            tab_stop: 4,
+            insert_spaces: true,
+            show_line_numbers: true,
+            highlight_current_line: true,
+            quit_times: 3,
+            message_timeout: 5,
+            scrolloff: 0,
+            sidescrolloff: 0,
+            trim_trailing_whitespace: true,
+            final_newline: true,
+            auto_close_brackets: true,
+            show_minimap: false,
+            continue_comments: true,
+            osc52_clipboard: false,
+            filler: String::from("~"),
+            wrap_cursor: false,
+            show_whitespace_errors: false,
+            status_bg_color: Rgb(239, 239, 239),
+            status_fg_color: Rgb(63, 63, 63),
+            status_format: None,
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `$XDG_CONFIG_HOME/editore/config.toml`, falling back to
+    /// `~/.config/editore/config.toml`. A missing file yields the defaults.
+    pub fn load() -> Result<Self, String> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(format!("Could not read {}: {error}", path.display())),
+        };
+        toml::from_str(&contents).map_err(|error| format!("Invalid {}: {error}", path.display()))
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// `$XDG_STATE_HOME/editore`, falling back to `~/.local/state/editore`, for
+/// what the editor remembers between runs
+pub fn state_dir() -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_dir.join("editore"))
+}
+
+/// The file called `name` in the state directory
+pub fn state_file(name: &str) -> Option<PathBuf> {
+    Some(state_dir()?.join(name))
+}
+
+/// How the state files name `file_name`: its absolute path, so that it is
+/// recognized when opened again from another directory
+pub fn state_key(file_name: &str) -> Option<String> {
+    let path = fs::canonicalize(file_name).ok()?;
+    Some(path.to_str()?.to_string())
+}
+
+/// `$XDG_CONFIG_HOME/editore`, falling back to `~/.config/editore`
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("editore"))
+}
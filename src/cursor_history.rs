@@ -0,0 +1,62 @@
+use std::fmt::Write;
+use std::fs;
+
+use crate::config;
+use crate::Position;
+
+/// How many files to remember the cursor position of
+const MAX_ENTRIES: usize = 1000;
+/// Where the positions are kept in the state directory
+const STATE_FILE: &str = "cursors";
+
+/// Where the cursor was when `file_name` was last saved or closed
+pub fn load(file_name: &str) -> Option<Position> {
+    let key = config::state_key(file_name)?;
+    let contents = fs::read_to_string(config::state_file(STATE_FILE)?).ok()?;
+    parse(&contents)
+        .into_iter()
+        .find(|(path, _)| *path == key)
+        .map(|(_, position)| position)
+}
+
+/// Records the cursor positions of the given files, replacing what was
+/// stored for them before
+pub fn store(positions: &[(&str, Position)]) -> Result<(), String> {
+    let Some(path) = config::state_file(STATE_FILE) else {
+        return Ok(());
+    };
+    let contents = fs::read_to_string(&path).unwrap_or_default();
+    let mut entries = parse(&contents);
+    for (file_name, position) in positions {
+        let Some(key) = config::state_key(file_name) else {
+            continue;
+        };
+        entries.retain(|(path, _)| *path != key);
+        entries.push((key, *position));
+    }
+    let skip = entries.len().saturating_sub(MAX_ENTRIES);
+
+    let mut contents = String::new();
+    for (key, Position { x, y }) in entries.into_iter().skip(skip) {
+        writeln!(contents, "{y}\t{x}\t{key}").ok();
+    }
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|error| format!("Could not create {}: {error}", directory.display()))?;
+    }
+    fs::write(&path, contents)
+        .map_err(|error| format!("Could not write {}: {error}", path.display()))
+}
+
+fn parse(contents: &str) -> Vec<(String, Position)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let y = fields.next()?.parse().ok()?;
+            let x = fields.next()?.parse().ok()?;
+            let path = fields.next()?.to_string();
+            Some((path, Position { x, y }))
+        })
+        .collect()
+}
@@ -1,35 +1,1612 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::convert::TryFrom;
 use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::HighLightManager;
+use crate::Position;
 use crate::Row;
+use crate::SearchDirection;
+
+/// Files bigger than this are loaded lazily as rows are needed
+const LAZY_LOAD_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// Files bigger than this many bytes are drawn without syntax highlighting
+const PLAIN_THRESHOLD: u64 = 4 * 1024 * 1024;
+/// How many rows to read ahead when rows past the loaded ones are needed
+const LOAD_CHUNK: usize = 1024;
+/// How many bytes at the start of a file are checked for NUL bytes to tell
+/// whether it is binary
+const BINARY_SAMPLE: usize = 8 * 1024;
+/// How many matches `find_all` looks for at most
+const MAX_MATCHES: usize = 10_000;
+
+/// The line terminator a document is saved with
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Picks the more common of the two terminators, preferring `\n` on a tie
+    fn dominant(crlf: usize, lf: usize) -> Self {
+        if crlf > lf {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+}
+
+/// A merge conflict left in the text by Git, given by the rows of its markers
+#[derive(Clone, Copy)]
+pub struct Conflict {
+    /// The `<<<<<<<` row
+    pub start: usize,
+    /// The `|||||||` row opening the common ancestor, in the diff3 style
+    pub base: Option<usize>,
+    /// The `=======` row
+    pub separator: usize,
+    /// The `>>>>>>>` row
+    pub end: usize,
+}
+
+impl Conflict {
+    /// The rows of our side, the one being merged into
+    pub fn ours(&self) -> Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.separator)
+    }
+
+    /// The rows of their side, the one being merged in
+    pub fn theirs(&self) -> Range<usize> {
+        self.separator + 1..self.end
+    }
+}
+
+const CONFLICT_START: &str = "<<<<<<<";
+const CONFLICT_BASE: &str = "|||||||";
+const CONFLICT_SEPARATOR: &str = "=======";
+const CONFLICT_END: &str = ">>>>>>>";
+
+/// How many indented rows to look at when guessing the indentation style
+const INDENT_SAMPLE: usize = 100;
+
+/// The indentation a file uses
+#[derive(Clone, Copy, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+impl Indent {
+    /// Guesses from leading whitespace, `None` when nothing is indented
+    fn detect(rows: &[Row]) -> Option<Self> {
+        let mut tabs = 0;
+        let mut widths = Vec::new();
+        let indented = rows
+            .iter()
+            .map(Row::as_str)
+            .filter(|line| line.starts_with([' ', '\t']) && !line.trim().is_empty())
+            .take(INDENT_SAMPLE);
+        for line in indented {
+            if line.starts_with('\t') {
+                tabs += 1;
+            } else {
+                widths.push(line.len() - line.trim_start_matches(' ').len());
+            }
+        }
+        if tabs == 0 && widths.is_empty() {
+            return None;
+        }
+        if tabs >= widths.len() {
+            return Some(Self::Tabs);
+        }
 
-#[derive(Default)]
+        // The most common step between indentation levels, or the smallest
+        // indentation when every line sits at the same level
+        let mut steps = [0_usize; 9];
+        let mut previous = 0;
+        for width in &widths {
+            if let Some(step) = width
+                .checked_sub(previous)
+                .filter(|step| (1..=8).contains(step))
+            {
+                steps[step] += 1;
+            }
+            previous = *width;
+        }
+        let size = (1..=8)
+            .max_by_key(|step| (steps[*step], *step))
+            .filter(|step| steps[*step] > 0)
+            .or_else(|| widths.iter().copied().min())?;
+        Some(Self::Spaces(size))
+    }
+}
+
+/// What `find_all` found last, kept while the pattern and the rows stay
+/// the same
+struct FoundMatches {
+    pattern: String,
+    revision: usize,
+    loaded: usize,
+    positions: Vec<Position>,
+}
+
+/// The part of a large file that hasn't been read into rows yet
+struct PendingRows {
+    reader: BufReader<fs::File>,
+    remaining: usize,
+}
+
+/// Holds at least one row, an empty document being a single empty line
+#[allow(clippy::struct_excessive_bools)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
+    /// Counts the edits made, so that `saved_revision` tells whether there
+    /// are unsaved changes
+    revision: usize,
+    saved_revision: usize,
+    /// The revision the swap file was last written at, to leave it alone
+    /// until there are new changes
+    swapped_revision: Option<usize>,
+    highlighted_until: usize,
+    pending: Option<PendingRows>,
+    line_ending: LineEnding,
+    mixed_line_endings: bool,
+    /// Whether the last row is followed by a line ending, as it was in the
+    /// file read, so saving doesn't add one that wasn't there. A new document
+    /// has none, so that it is saved as an empty file until typed into.
+    final_line_ending: bool,
+    indent: Option<Indent>,
+    binary: bool,
+    /// Whether bytes that aren't valid UTF-8 were replaced when reading the
+    /// file, and so would be lost on saving
+    lossy: bool,
+    /// When the file was last changed on disk as far as the document knows,
+    /// to tell when something else changes it
+    disk_modified: Option<SystemTime>,
+    /// Whether the rows list the entries of the directory named by
+    /// `file_name` rather than the lines of a file
+    directory: bool,
+    /// Whether rows are drawn in the theme's plain colors instead of being
+    /// syntax highlighted, which is much cheaper
+    plain: bool,
+    found: Option<FoundMatches>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rows: vec![Row::default()],
+            file_name: None,
+            revision: 0,
+            saved_revision: 0,
+            swapped_revision: None,
+            highlighted_until: 0,
+            pending: None,
+            line_ending: LineEnding::default(),
+            mixed_line_endings: false,
+            final_line_ending: false,
+            indent: None,
+            binary: false,
+            lossy: false,
+            disk_modified: None,
+            directory: false,
+            plain: false,
+            found: None,
+        }
+    }
 }
 
 impl Document {
-    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
+    /// Opens `filename`, or starts an empty document under that name if the
+    /// file doesn't exist yet
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let large = match fs::metadata(filename) {
+            Ok(metadata) if metadata.is_dir() => return Self::list_directory(filename),
+            Ok(metadata) if metadata.len() > LAZY_LOAD_THRESHOLD => {
+                return Self::open_lazily(filename)
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    file_name: Some(filename.to_string()),
+                    ..Self::default()
+                })
+            }
+            Ok(metadata) => metadata.len() > PLAIN_THRESHOLD,
+            Err(_) => false,
+        };
+        let bytes = fs::read(filename)?;
+        // Binary files and invalid UTF-8 are shown as best they can be
+        // rather than refused
+        let contents = String::from_utf8_lossy(&bytes);
+        Ok(Self {
+            file_name: Some(filename.to_string()),
+            binary: is_binary(&bytes),
+            lossy: matches!(contents, Cow::Owned(_)),
+            disk_modified: modified_time(filename),
+            plain: large,
+            ..Self::from_contents(&contents)
+        })
+    }
+
+    /// Lists the entries of the directory `filename`, one per row, with
+    /// `../` first and a `/` after the names of directories
+    fn list_directory(filename: &str) -> Result<Self, Error> {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(filename)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() {
+                directories.push(format!("{name}/"));
+            } else {
+                files.push(name);
+            }
+        }
+        directories.sort();
+        files.sort();
+        let mut rows = Vec::new();
+        if Path::new(filename).parent().is_some() {
+            rows.push(Row::from("../"));
+        }
+        rows.extend(
+            directories
+                .iter()
+                .chain(&files)
+                .map(|name| Row::from(name.as_str())),
+        );
+        if rows.is_empty() {
+            rows.push(Row::default());
+        }
+        Ok(Self {
+            rows,
+            file_name: Some(filename.to_string()),
+            directory: true,
+            ..Self::default()
+        })
+    }
+
+    /// Reads a document without a file name from `reader`, such as piped
+    /// input. It starts out modified since its contents aren't saved anywhere.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let contents = String::from_utf8_lossy(&bytes);
+        Ok(Self {
+            revision: 1,
+            lossy: matches!(contents, Cow::Owned(_)),
+            ..Self::from_contents(&contents)
+        })
+    }
+
+    fn from_contents(contents: &str) -> Self {
+        let lf = contents.matches('\n').count();
+        let crlf = contents.matches("\r\n").count();
         let mut rows = Vec::new();
         for value in contents.lines() {
             rows.push(Row::from(value));
         }
-        Ok(Self {
+        if rows.is_empty() {
+            rows.push(Row::default());
+        }
+        Self {
+            indent: Indent::detect(&rows),
             rows,
+            line_ending: LineEnding::dominant(crlf, lf - crlf),
+            mixed_line_endings: crlf > 0 && crlf < lf,
+            final_line_ending: contents.ends_with('\n'),
+            ..Self::default()
+        }
+    }
+
+    /// Only counts the lines of `filename` up front, rows are read later by
+    /// `load_rows` as the editor reaches them
+    fn open_lazily(filename: &str) -> Result<Self, Error> {
+        let mut reader = BufReader::new(fs::File::open(filename)?);
+        let mut lines = 0;
+        let mut crlf = 0;
+        let mut previous = 0;
+        let mut ends_with_newline = true;
+        let mut binary = false;
+        let mut scanned = 0;
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            if scanned < BINARY_SAMPLE {
+                binary |= is_binary(&buffer[..cmp::min(buffer.len(), BINARY_SAMPLE - scanned)]);
+                scanned += buffer.len();
+            }
+            for byte in buffer {
+                if *byte == b'\n' {
+                    lines += 1;
+                    if previous == b'\r' {
+                        crlf += 1;
+                    }
+                }
+                previous = *byte;
+            }
+            ends_with_newline = buffer.ends_with(b"\n");
+            let read = buffer.len();
+            reader.consume(read);
+        }
+        if !ends_with_newline {
+            lines += 1;
+        }
+
+        Ok(Self {
+            rows: Vec::new(),
             file_name: Some(filename.to_string()),
+            pending: Some(PendingRows {
+                reader: BufReader::new(fs::File::open(filename)?),
+                remaining: lines,
+            }),
+            line_ending: LineEnding::dominant(crlf, lines - crlf),
+            mixed_line_endings: crlf > 0 && crlf < lines,
+            final_line_ending: ends_with_newline,
+            binary,
+            disk_modified: modified_time(filename),
+            plain: true,
+            ..Self::default()
         })
     }
 
+    /// Makes sure at least the first `until` rows are read from disk
+    pub fn load_rows(&mut self, until: usize) {
+        if self.rows.len() >= until {
+            return;
+        }
+        let until = until.saturating_add(LOAD_CHUNK);
+        while self.rows.len() < until {
+            let Some(pending) = self.pending.as_mut() else {
+                return;
+            };
+            let mut line = Vec::new();
+            let read = pending.reader.read_until(b'\n', &mut line).unwrap_or(0);
+            if read == 0 || pending.remaining <= 1 {
+                self.pending = None;
+            } else {
+                pending.remaining -= 1;
+            }
+            if read == 0 {
+                return;
+            }
+            if line.ends_with(b"\n") {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+            let text = String::from_utf8_lossy(&line);
+            self.lossy |= matches!(text, Cow::Owned(_));
+            self.rows.push(Row::from(&text[..]));
+        }
+    }
+
+    pub fn insert(&mut self, at: &Position, c: char) {
+        self.load_rows(at.y.saturating_add(2));
+        if at.y > self.len() {
+            return;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if c == '\n' {
+            self.insert_newline(at);
+            return;
+        }
+        if at.y == self.len() {
+            let mut row = Row::default();
+            row.insert(0, c);
+            self.rows.push(row);
+        } else if let Some(row) = self.row_mut(at.y) {
+            row.insert(at.x, c);
+        }
+    }
+
+    /// Replaces the grapheme at `at` with `c` and returns it, or inserts `c`
+    /// when `at` is at the end of its row
+    pub fn overwrite(&mut self, at: &Position, c: char) -> Option<String> {
+        self.load_rows(at.y.saturating_add(1));
+        let Some(replaced) = self
+            .row(at.y)
+            .and_then(|row| row.grapheme(at.x))
+            .map(str::to_string)
+        else {
+            self.insert(at, c);
+            return None;
+        };
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if let Some(row) = self.row_mut(at.y) {
+            row.delete(at.x);
+            row.insert(at.x, c);
+        }
+        Some(replaced)
+    }
+
+    pub fn insert_str(&mut self, at: &Position, string: &str) {
+        self.load_rows(at.y.saturating_add(2));
+        if at.y > self.len() {
+            return;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if at.y == self.len() {
+            self.rows.push(Row::from(string));
+        } else if let Some(row) = self.row_mut(at.y) {
+            row.insert_str(at.x, string);
+        }
+    }
+
+    /// Splits the row at `at`, carrying its indentation over to the new row.
+    /// Returns the length of that indentation, where the cursor should go.
+    pub fn insert_newline(&mut self, at: &Position) -> usize {
+        self.load_rows(at.y.saturating_add(2));
+        if at.y > self.len() {
+            return 0;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if at.y == self.len() {
+            self.rows.push(Row::default());
+            return 0;
+        }
+        let Some(row) = self.row_mut(at.y) else {
+            return 0;
+        };
+        let indent_len = cmp::min(row.first_non_blank(), at.x);
+        let indent = row.slice(0, indent_len);
+        let mut new_row = row.split(at.x);
+        new_row.insert_str(0, &indent);
+        self.rows.insert(at.y + 1, new_row);
+        indent_len
+    }
+
+    /// Inserts `text`, which may span several lines, returning the position
+    /// right after it. Unlike typing Enter, new lines aren't indented.
+    pub fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        let mut at = *at;
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.split_row(&at);
+                at = Position {
+                    x: 0,
+                    y: at.y.saturating_add(1),
+                };
+            }
+            if !line.is_empty() {
+                self.insert_str(&at, line);
+                at.x = at.x.saturating_add(line.graphemes(true).count());
+            }
+        }
+        at
+    }
+
+    fn split_row(&mut self, at: &Position) {
+        self.load_rows(at.y.saturating_add(2));
+        if at.y > self.len() {
+            return;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if at.y == self.len() {
+            self.rows.push(Row::default());
+            return;
+        }
+        if let Some(row) = self.row_mut(at.y) {
+            let new_row = row.split(at.x);
+            self.rows.insert(at.y + 1, new_row);
+        }
+    }
+
+    /// The text from `start` up to `end`, with rows separated by `\n`
+    pub fn text_between(&mut self, start: &Position, end: &Position) -> String {
+        self.load_rows(end.y.saturating_add(1));
+        let Some((start, end)) = self.clamp_range(start, end) else {
+            return String::new();
+        };
+        if start.y == end.y {
+            return self.rows[start.y].slice(start.x, end.x);
+        }
+        let mut text = self.rows[start.y].slice(start.x, self.rows[start.y].len());
+        for row in &self.rows[start.y + 1..end.y] {
+            text.push('\n');
+            text.push_str(row.as_str());
+        }
+        text.push('\n');
+        text.push_str(&self.rows[end.y].slice(0, end.x));
+        text
+    }
+
+    /// Removes the text from `start` up to `end`, returning it
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        let text = self.text_between(start, end);
+        let Some((start, end)) = self
+            .clamp_range(start, end)
+            .filter(|(start, end)| start != end)
+        else {
+            return text;
+        };
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(start.y);
+        let tail = self.rows[end.y].slice(end.x, self.rows[end.y].len());
+        self.rows.drain(start.y + 1..=end.y);
+        if let Some(row) = self.row_mut(start.y) {
+            row.replace(start.x, row.len().saturating_sub(start.x), &tail);
+        }
+        text
+    }
+
+    /// Keeps a range within the loaded rows, `None` if nothing is left of it
+    fn clamp_range(&self, start: &Position, end: &Position) -> Option<(Position, Position)> {
+        let last = self.rows.len().checked_sub(1)?;
+        if start.y > last {
+            return None;
+        }
+        let end = if end.y > last {
+            Position {
+                x: self.rows[last].len(),
+                y: last,
+            }
+        } else {
+            Position {
+                x: cmp::min(end.x, self.rows[end.y].len()),
+                y: end.y,
+            }
+        };
+        let start = Position {
+            x: cmp::min(start.x, self.rows[start.y].len()),
+            y: start.y,
+        };
+        Some((start, end))
+    }
+
+    pub fn delete(&mut self, at: &Position) {
+        self.load_rows(at.y.saturating_add(2));
+        let len = self.len();
+        if at.y >= len {
+            return;
+        }
+        let row_len = self.row(at.y).map_or(0, Row::len);
+        // Nothing follows the end of the last row
+        if at.x >= row_len && at.y + 1 == len {
+            return;
+        }
+        let at_end = at.x == row_len;
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(at.y);
+        if at_end {
+            let next_row = self.rows.remove(at.y + 1);
+            if let Some(row) = self.row_mut(at.y) {
+                row.append(&next_row);
+            }
+        } else if let Some(row) = self.row_mut(at.y) {
+            row.delete(at.x);
+        }
+    }
+
+    /// The closest row from `y` in `direction` that isn't blank and is
+    /// indented no deeper than row `y`, for jumping between blocks of code
+    pub fn next_block(
+        &mut self,
+        y: usize,
+        direction: SearchDirection,
+        tab_stop: usize,
+    ) -> Option<usize> {
+        self.load_rows(y.saturating_add(1));
+        let current = indent_width(self.row(y)?, tab_stop);
+        let mut y = y;
+        loop {
+            y = match direction {
+                SearchDirection::Forward => y.saturating_add(1),
+                SearchDirection::Backward => y.checked_sub(1)?,
+            };
+            self.load_rows(y.saturating_add(1));
+            let row = self.row(y)?;
+            if !row.is_blank() && indent_width(row, tab_stop) <= current {
+                return Some(y);
+            }
+        }
+    }
+
+    /// The rows after `y` indented deeper than it, along with the blank
+    /// rows between them, making up the block that row `y` starts
+    pub fn block_below(&mut self, y: usize, tab_stop: usize) -> Option<Range<usize>> {
+        self.load_rows(y.saturating_add(1));
+        let row = self.row(y).filter(|row| !row.is_blank())?;
+        let indent = indent_width(row, tab_stop);
+        let mut end = y.saturating_add(1);
+        let mut next = end;
+        loop {
+            self.load_rows(next.saturating_add(1));
+            let Some(row) = self.row(next) else {
+                break;
+            };
+            if !row.is_blank() {
+                if indent_width(row, tab_stop) <= indent {
+                    break;
+                }
+                end = next.saturating_add(1);
+            }
+            next = next.saturating_add(1);
+        }
+        let start = y.saturating_add(1);
+        (end > start).then_some(start..end)
+    }
+
+    /// The block that row `y` starts or else is part of, along with the row
+    /// starting it
+    pub fn block_around(&mut self, y: usize, tab_stop: usize) -> Option<(usize, Range<usize>)> {
+        if let Some(block) = self.block_below(y, tab_stop) {
+            return Some((y, block));
+        }
+        let indent = self
+            .row(y)
+            .filter(|row| !row.is_blank())
+            .map_or(usize::MAX, |row| indent_width(row, tab_stop));
+        let start = (0..y).rev().find(|start| {
+            self.row(*start)
+                .is_some_and(|row| !row.is_blank() && indent_width(row, tab_stop) < indent)
+        })?;
+        let block = self.block_below(start, tab_stop)?;
+        block.contains(&y).then_some((start, block))
+    }
+
+    /// Whether row `y` is one of the lines marking a merge conflict
+    pub fn is_conflict_marker(&self, y: usize) -> bool {
+        let markers = [
+            CONFLICT_START,
+            CONFLICT_BASE,
+            CONFLICT_SEPARATOR,
+            CONFLICT_END,
+        ];
+        self.row(y)
+            .is_some_and(|row| markers.iter().any(|marker| is_marker(row, marker)))
+    }
+
+    /// The row starting the closest merge conflict from `y` in `direction`
+    pub fn next_conflict(&mut self, y: usize, direction: SearchDirection) -> Option<usize> {
+        let mut y = y;
+        loop {
+            y = match direction {
+                SearchDirection::Forward => y.saturating_add(1),
+                SearchDirection::Backward => y.checked_sub(1)?,
+            };
+            self.load_rows(y.saturating_add(1));
+            if is_marker(self.row(y)?, CONFLICT_START) {
+                return Some(y);
+            }
+        }
+    }
+
+    /// The merge conflict that row `y` is part of
+    pub fn conflict_at(&mut self, y: usize) -> Option<Conflict> {
+        let start = (0..=y).rev().find_map(|start| {
+            let row = self.row(start)?;
+            if is_marker(row, CONFLICT_START) {
+                Some(Some(start))
+            } else if start < y && is_marker(row, CONFLICT_END) {
+                // Past the end of an earlier conflict
+                Some(None)
+            } else {
+                None
+            }
+        })??;
+        let mut base = None;
+        let mut separator = None;
+        for end in start + 1.. {
+            self.load_rows(end.saturating_add(1));
+            let row = self.row(end)?;
+            if is_marker(row, CONFLICT_BASE) && separator.is_none() {
+                base = Some(end);
+            } else if is_marker(row, CONFLICT_SEPARATOR) && separator.is_none() {
+                separator = Some(end);
+            } else if is_marker(row, CONFLICT_END) {
+                return Some(Conflict {
+                    start,
+                    base,
+                    separator: separator?,
+                    end,
+                })
+                .filter(|_| y <= end);
+            } else if is_marker(row, CONFLICT_START) {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Inserts a row holding `text` before row `y`
+    pub fn insert_row(&mut self, y: usize, text: &str) {
+        self.load_rows(y.saturating_add(1));
+        if y > self.len() {
+            return;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(y);
+        self.rows.insert(y, Row::from(text));
+    }
+
+    /// Removes row `y`, returning its contents
+    pub fn remove_row(&mut self, y: usize) -> Option<String> {
+        self.load_rows(y.saturating_add(1));
+        if y >= self.rows.len() {
+            return None;
+        }
+        self.revision = self.revision.saturating_add(1);
+        self.unhighlight_rows(y);
+        let row = self.rows.remove(y);
+        if self.rows.is_empty() && self.pending.is_none() {
+            self.rows.push(Row::default());
+        }
+        Some(row.as_str().to_string())
+    }
+
+    /// Inserts a copy of row `y` below it, returning the copied text
+    pub fn duplicate_row(&mut self, y: usize) -> Option<String> {
+        self.load_rows(y.saturating_add(1));
+        let text = self.rows.get(y)?.as_str().to_string();
+        self.insert_row(y.saturating_add(1), &text);
+        Some(text)
+    }
+
+    /// Swaps row `y` with its neighbor in `direction`, returning the row it
+    /// ended up at and its text
+    pub fn move_row(&mut self, y: usize, direction: SearchDirection) -> Option<(usize, String)> {
+        let target = match direction {
+            SearchDirection::Forward => y.checked_add(1).filter(|target| *target < self.len())?,
+            SearchDirection::Backward => y.checked_sub(1)?,
+        };
+        let text = self.remove_row(y)?;
+        self.insert_row(target, &text);
+        Some((target, text))
+    }
+
+    /// Adds `indent` to the start of row `y`, leaving it alone if it is empty.
+    /// Returns whether anything was added.
+    pub fn indent_row(&mut self, y: usize, indent: &str) -> bool {
+        self.load_rows(y.saturating_add(1));
+        if self.rows.get(y).is_none_or(Row::is_empty) {
+            return false;
+        }
+        self.insert_str(&Position { x: 0, y }, indent);
+        true
+    }
+
+    /// Removes one level of indentation from the start of row `y`, either a
+    /// tab or up to `width` spaces, and returns it
+    pub fn dedent_row(&mut self, y: usize, width: usize) -> Option<String> {
+        self.load_rows(y.saturating_add(1));
+        let row = self.rows.get(y)?;
+        let removed = if row.as_str().starts_with('\t') {
+            "\t".to_string()
+        } else {
+            let spaces = row.as_str().chars().take(width).take_while(|c| *c == ' ');
+            spaces.collect()
+        };
+        if removed.is_empty() {
+            return None;
+        }
+        self.delete_range(
+            &Position { x: 0, y },
+            &Position {
+                x: removed.len(),
+                y,
+            },
+        );
+        Some(removed)
+    }
+
+    /// Whether row `y` starts with the line comment `token` after its
+    /// indentation
+    pub fn is_commented(&self, y: usize, token: &str) -> bool {
+        self.rows
+            .get(y)
+            .is_some_and(|row| row.as_str().trim_start().starts_with(token))
+    }
+
+    /// Comments out row `y` by inserting `token` and a space after its
+    /// indentation, leaving blank rows alone. Returns where the text went.
+    pub fn comment_row(&mut self, y: usize, token: &str) -> Option<(Position, String)> {
+        self.load_rows(y.saturating_add(1));
+        let row = self.rows.get(y).filter(|row| !row.is_blank())?;
+        let at = Position {
+            x: row.first_non_blank(),
+            y,
+        };
+        let text = format!("{token} ");
+        self.insert_str(&at, &text);
+        Some((at, text))
+    }
+
+    /// Removes the line comment `token` after the indentation of row `y`,
+    /// along with a space following it, and returns where it was
+    pub fn uncomment_row(&mut self, y: usize, token: &str) -> Option<(Position, String)> {
+        self.load_rows(y.saturating_add(1));
+        if !self.is_commented(y, token) {
+            return None;
+        }
+        let row = self.rows.get(y)?;
+        let x = row.first_non_blank();
+        let rest = row.as_str().trim_start()[token.len()..].to_string();
+        let len = token.graphemes(true).count() + usize::from(rest.starts_with(' '));
+        let at = Position { x, y };
+        let text = self.delete_range(&at, &Position { x: x + len, y });
+        Some((at, text))
+    }
+
+    /// Replaces the occurrence of `query` starting at `at` with `replacement`
+    pub fn replace(&mut self, at: &Position, query: &str, replacement: &str) {
+        self.load_rows(at.y.saturating_add(1));
+        if let Some(row) = self.row_mut(at.y) {
+            row.replace(at.x, query.graphemes(true).count(), replacement);
+            self.revision = self.revision.saturating_add(1);
+            self.unhighlight_rows(at.y);
+        }
+    }
+
+    pub fn find(
+        &mut self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<Position> {
+        self.find_with(at, direction, |row, x| row.find(query, x, direction))
+    }
+
+    /// Finds the start of the next non-empty match of `re` from `at`
+    pub fn find_regex(
+        &mut self,
+        re: &Regex,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<Position> {
+        self.find_with(at, direction, |row, x| row.find_regex(re, x, direction))
+    }
+
+    /// The start of every non-empty match of `re` in the rows read so far,
+    /// up to `MAX_MATCHES` of them, and whether that is all there are. They
+    /// are only looked for again once the pattern or the rows change.
+    pub fn find_all(&mut self, re: &Regex) -> (&[Position], bool) {
+        let fresh = self.found.as_ref().is_some_and(|found| {
+            found.pattern == re.as_str()
+                && found.revision == self.revision
+                && found.loaded == self.rows.len()
+        });
+        if !fresh {
+            let positions = self
+                .rows
+                .iter()
+                .enumerate()
+                .flat_map(|(y, row)| {
+                    row.find_all_regex(re)
+                        .into_iter()
+                        .map(move |range| Position { x: range.start, y })
+                })
+                .take(MAX_MATCHES)
+                .collect();
+            self.found = Some(FoundMatches {
+                pattern: re.as_str().to_string(),
+                revision: self.revision,
+                loaded: self.rows.len(),
+                positions,
+            });
+        }
+        let complete = self.pending.is_none();
+        let positions = self
+            .found
+            .as_ref()
+            .map_or(&[][..], |found| &found.positions[..]);
+        (positions, complete && positions.len() < MAX_MATCHES)
+    }
+
+    /// Walks the rows from `at` in `direction`, asking `find_in_row` for a
+    /// match from the given grapheme index within each
+    fn find_with<F>(
+        &mut self,
+        at: &Position,
+        direction: SearchDirection,
+        find_in_row: F,
+    ) -> Option<Position>
+    where
+        F: Fn(&Row, usize) -> Option<usize>,
+    {
+        if at.y >= self.len() {
+            return None;
+        }
+        let mut position = Position { x: at.x, y: at.y };
+
+        let start = if direction == SearchDirection::Forward {
+            at.y
+        } else {
+            0
+        };
+        let end = if direction == SearchDirection::Forward {
+            self.len()
+        } else {
+            at.y.saturating_add(1)
+        };
+
+        for _ in start..end {
+            self.load_rows(position.y.saturating_add(1));
+            let row = self.rows.get(position.y)?;
+            if let Some(x) = find_in_row(row, position.x) {
+                position.x = x;
+                return Some(position);
+            }
+            if direction == SearchDirection::Forward {
+                position.y = position.y.saturating_add(1);
+                position.x = 0;
+            } else {
+                position.y = position.y.saturating_sub(1);
+                position.x = self.rows[position.y].len();
+            }
+        }
+        None
+    }
+
+    /// Finds the bracket matching the one at `at`, accounting for nesting.
+    /// Only rows that are already loaded are searched.
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let bracket = self.rows.get(at.y)?.grapheme(at.x)?;
+        let (target, direction) = match bracket {
+            "(" => (")", SearchDirection::Forward),
+            "[" => ("]", SearchDirection::Forward),
+            "{" => ("}", SearchDirection::Forward),
+            ")" => ("(", SearchDirection::Backward),
+            "]" => ("[", SearchDirection::Backward),
+            "}" => ("{", SearchDirection::Backward),
+            _ => return None,
+        };
+
+        let mut depth = 0;
+        let mut y = at.y;
+        let mut x = if direction == SearchDirection::Forward {
+            at.x.saturating_add(1)
+        } else {
+            at.x
+        };
+        loop {
+            let row = self.rows.get(y)?;
+            if let Some(x) = row.find_bracket(bracket, target, x, direction, &mut depth) {
+                return Some(Position { x, y });
+            }
+            if direction == SearchDirection::Forward {
+                y = y.saturating_add(1);
+                x = 0;
+            } else {
+                y = y.checked_sub(1)?;
+                x = self.rows[y].len();
+            }
+        }
+    }
+
+    pub fn trim_trailing_whitespace(&mut self) -> Vec<(Position, String)> {
+        self.load_rows(self.len());
+        let mut trimmed = Vec::new();
+        for y in 0..self.rows.len() {
+            if let Some(text) = self.row_mut(y).and_then(Row::trim_end) {
+                self.revision = self.revision.saturating_add(1);
+                self.unhighlight_rows(y);
+                trimmed.push((
+                    Position {
+                        x: self.rows[y].len(),
+                        y,
+                    },
+                    text,
+                ));
+            }
+        }
+        trimmed
+    }
+
+    /// Removes the blank rows at the end of the document, keeping at least
+    /// one, and returns the line breaks removed along with where they were
+    pub fn trim_trailing_blank_rows(&mut self) -> Option<(Position, String)> {
+        self.load_rows(self.len());
+        let mut removed = String::new();
+        while self.rows.len() > 1 && self.rows.last().is_some_and(Row::is_empty) {
+            self.rows.pop();
+            self.revision = self.revision.saturating_add(1);
+            removed.push('\n');
+        }
+        self.unhighlight_rows(self.rows.len());
+        let y = self.rows.len() - 1;
+        let at = Position {
+            x: self.rows[y].len(),
+            y,
+        };
+        (!removed.is_empty()).then_some((at, removed))
+    }
+
+    /// Makes saving end the file with a line ending, even if the file read
+    /// didn't
+    pub fn add_final_line_ending(&mut self) {
+        self.final_line_ending = true;
+    }
+
+    /// Writes a temporary file next to the file and renames it over the
+    /// file, so that an interrupted save can't leave it half written. The
+    /// file keeps its permissions and, where allowed, its owner. Returns the
+    /// number of bytes written.
+    pub fn save(&mut self) -> Result<usize, Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(0);
+        };
+        // Saving through a symlink replaces the file it points to
+        let path = fs::canonicalize(&file_name).unwrap_or_else(|_| PathBuf::from(&file_name));
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let temp = path.with_file_name(format!(".{name}.editore-tmp"));
+        let written = self.write_replacing(&temp, &path);
+        if written.is_err() {
+            fs::remove_file(&temp).ok();
+        }
+        let written = written?;
+        self.saved_revision = self.revision;
+        self.mixed_line_endings = false;
+        self.lossy = false;
+        self.disk_modified = modified_time(&file_name);
+        self.remove_swap();
+        self.swapped_revision = None;
+        Ok(written)
+    }
+
+    fn write_replacing(&mut self, temp: &Path, path: &Path) -> Result<usize, Error> {
+        let file = fs::File::create(temp)?;
+        let written = self.write_to(&file)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            preserve_owner(temp, &metadata);
+            fs::set_permissions(temp, metadata.permissions())?;
+        }
+        fs::rename(temp, path)?;
+        Ok(written)
+    }
+
+    fn write_to(&mut self, file: &fs::File) -> Result<usize, Error> {
+        self.load_rows(self.len());
+        self.write_loaded_to(file)
+    }
+
+    /// Writes the rows read so far, followed by the part of the file not
+    /// read yet copied as it is, so that a large file isn't read into rows
+    /// just to be written out
+    fn write_loaded_to(&mut self, file: &fs::File) -> Result<usize, Error> {
+        let mut writer = BufWriter::new(file);
+        let line_ending = self.line_ending.as_str().as_bytes();
+        let more = self.pending.is_some();
+        let mut written = 0;
+        for (y, row) in self.rows.iter().enumerate() {
+            writer.write_all(row.as_bytes())?;
+            written += row.as_bytes().len();
+            if more || y + 1 < self.rows.len() || self.final_line_ending {
+                writer.write_all(line_ending)?;
+                written += line_ending.len();
+            }
+        }
+        if let (Some(pending), Some(file_name)) = (self.pending.as_mut(), &self.file_name) {
+            let mut unread = fs::File::open(file_name)?;
+            unread.seek(SeekFrom::Start(pending.reader.stream_position()?))?;
+            let copied = io::copy(&mut unread, &mut writer)?;
+            written += usize::try_from(copied).unwrap_or(usize::MAX);
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Where unsaved changes are written to so they survive a crash,
+    /// `.name.editore-swap` next to the file
+    fn swap_path(&self) -> Option<PathBuf> {
+        let path = Path::new(self.file_name.as_deref()?);
+        let name = path.file_name()?.to_str()?;
+        Some(path.with_file_name(format!(".{name}.editore-swap")))
+    }
+
+    /// Writes the current contents to the swap file without saving the
+    /// document itself, unless they haven't changed since the last time.
+    /// The swap file can be read by no more people than the file itself.
+    pub fn write_swap(&mut self) -> Result<(), Error> {
+        let Some(path) = self.swap_path() else {
+            return Ok(());
+        };
+        if self.swapped_revision == Some(self.revision) {
+            return Ok(());
+        }
+        let original = self
+            .file_name
+            .as_deref()
+            .and_then(|file_name| fs::metadata(file_name).ok());
+        self.write_loaded_to(&create_swap(&path, original.as_ref())?)?;
+        self.swapped_revision = Some(self.revision);
+        Ok(())
+    }
+
+    pub fn remove_swap(&self) {
+        if let Some(path) = self.swap_path() {
+            fs::remove_file(path).ok();
+        }
+    }
+
+    /// Whether a swap file was left behind, by a crash or by another editor
+    /// still having the file open
+    pub fn has_swap(&self) -> bool {
+        self.swap_path().is_some_and(|path| path.is_file())
+    }
+
+    /// Replaces the contents with those of the swap file, leaving the
+    /// document modified until it is saved
+    pub fn recover(&mut self) -> Result<(), Error> {
+        let Some(path) = self.swap_path() else {
+            return Ok(());
+        };
+        let contents = fs::read_to_string(path)?;
+        let recovered = Self::from_contents(&contents);
+        self.rows = recovered.rows;
+        self.pending = None;
+        self.final_line_ending = recovered.final_line_ending;
+        self.indent = recovered.indent;
+        self.highlighted_until = 0;
+        self.revision = self.revision.saturating_add(1);
+        Ok(())
+    }
+
+    pub fn highlight(&mut self, h: &HighLightManager, until: usize) {
+        let until = cmp::min(until, self.rows.len());
+        if self.highlighted_until >= until {
+            return;
+        }
+        if self.plain {
+            let style = h.plain_style();
+            for row in &mut self.rows[self.highlighted_until..until] {
+                row.highlight_plain(style);
+            }
+            self.highlighted_until = until;
+            return;
+        }
+        let mut state = self
+            .highlighted_until
+            .checked_sub(1)
+            .and_then(|y| self.rows[y].highlight_state())
+            .map_or_else(|| h.start_state(), Clone::clone);
+        for row in &mut self.rows[self.highlighted_until..until] {
+            row.highlight(h, &mut state);
+        }
+        self.highlighted_until = until;
+    }
+
+    pub fn reset_highlighting(&mut self) {
+        self.highlighted_until = 0;
+    }
+
+    /// Whether syntax highlighting is off, as it is for big files
+    pub fn is_plain(&self) -> bool {
+        self.plain
+    }
+
+    pub fn set_plain(&mut self, plain: bool) {
+        self.plain = plain;
+        self.reset_highlighting();
+    }
+
+    fn unhighlight_rows(&mut self, start: usize) {
+        self.highlighted_until = cmp::min(self.highlighted_until, start);
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
 
+    /// A loaded row to edit in place. It stays private so that every edit
+    /// goes through a method that bumps the revision and rehighlights.
+    fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.rows.get_mut(index)
+    }
+
+    /// Whether the document is a single empty line
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 1 && self.rows.first().is_some_and(Row::is_empty)
     }
 
+    /// Number of lines in the document, including those not loaded yet
     pub fn len(&self) -> usize {
-        self.rows.len()
+        self.rows.len() + self.pending.as_ref().map_or(0, |pending| pending.remaining)
+    }
+
+    /// The indentation style detected when the file was read, if any
+    pub fn indent(&self) -> Option<Indent> {
+        self.indent
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Whether the file mixed `\n` and `\r\n` when it was opened; it is
+    /// normalized to the dominant one on save
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    /// Whether the start of the file had NUL bytes when it was opened
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Whether the document lists a directory
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    /// The path of the entry listed on row `y` of a directory listing, going
+    /// up a level for `../`
+    pub fn entry_path(&self, y: usize) -> Option<PathBuf> {
+        let directory = Path::new(self.file_name.as_deref().filter(|_| self.directory)?);
+        let name = self.rows.get(y)?.as_str();
+        if name.is_empty() {
+            return None;
+        }
+        if name == "../" {
+            // `.` and `..` have no name to drop, so they are gone up from
+            return Some(match directory.parent() {
+                Some(parent) if directory.file_name().is_some() => {
+                    if parent.as_os_str().is_empty() {
+                        PathBuf::from(".")
+                    } else {
+                        parent.to_path_buf()
+                    }
+                }
+                _ => directory.join(".."),
+            });
+        }
+        let name = name.trim_end_matches('/');
+        Some(if directory == Path::new(".") {
+            PathBuf::from(name)
+        } else {
+            directory.join(name)
+        })
+    }
+
+    /// Whether the file changed on disk since it was opened, saved or last
+    /// checked, so each change is only told about once
+    pub fn changed_on_disk(&mut self) -> bool {
+        let Some(modified) = self.file_name.as_deref().and_then(modified_time) else {
+            return false;
+        };
+        let changed = self.disk_modified.is_some_and(|known| known != modified);
+        self.disk_modified = Some(modified);
+        changed
+    }
+
+    /// Whether bytes that aren't valid UTF-8 were replaced when reading the
+    /// file. Rows of big files are checked as they are loaded.
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    /// The encoding the file was read with, for showing to the user
+    pub fn encoding(&self) -> &'static str {
+        if self.lossy {
+            "utf-8 (lossy)"
+        } else {
+            "utf-8"
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.revision != self.saved_revision
+    }
+
+    /// Changes with every edit, for telling whether the contents changed
+    /// since some earlier point
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+}
+
+/// Whether `row` is a conflict marker like `<<<<<<<`, which may be followed
+/// by a space and the name of a branch
+fn is_marker(row: &Row, marker: &str) -> bool {
+    row.as_str()
+        .strip_prefix(marker)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+}
+
+/// The screen columns taken by the indentation of `row`
+fn indent_width(row: &Row, tab_stop: usize) -> usize {
+    row.render_x(row.first_non_blank(), tab_stop)
+}
+
+fn modified_time(file_name: &str) -> Option<SystemTime> {
+    fs::metadata(file_name).ok()?.modified().ok()
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..cmp::min(bytes.len(), BINARY_SAMPLE)].contains(&0)
+}
+
+/// Gives `path` the owner of the file it replaces. Only root may give files
+/// away, so for others this quietly leaves them owned by the user.
+#[cfg(unix)]
+fn preserve_owner(path: &Path, metadata: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid())).ok();
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_path: &Path, _metadata: &fs::Metadata) {}
+
+/// Creates the swap file at `path` with the permissions and owner of the
+/// `original` file, or readable only by the user when there is none yet
+#[cfg(unix)]
+fn create_swap(path: &Path, original: Option<&fs::Metadata>) -> Result<fs::File, Error> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mode = original.map_or(0o600, |metadata| metadata.permissions().mode() & 0o777);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    // A swap file left from before keeps the mode it was created with
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    if let Some(metadata) = original {
+        preserve_owner(path, metadata);
+    }
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_swap(path: &Path, _original: Option<&fs::Metadata>) -> Result<fs::File, Error> {
+    fs::File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn document(text: &str) -> Document {
+        Document::from_reader(text.as_bytes()).unwrap()
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        (0..document.len())
+            .filter_map(|y| document.row(y))
+            .map(Row::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn an_empty_document_has_one_empty_row() {
+        for document in [Document::default(), document("")] {
+            assert_eq!(document.len(), 1);
+            assert!(document.is_empty());
+            assert_eq!(lines(&document), [""]);
+        }
+        assert!(!document("\n\n").is_empty());
+        assert!(!document("a").is_empty());
+    }
+
+    #[test]
+    fn deleting_in_an_empty_document_changes_nothing() {
+        let mut document = Document::default();
+        document.delete(&Position { x: 0, y: 0 });
+        document.delete(&Position { x: 3, y: 2 });
+        assert_eq!(
+            document.delete_range(&Position { x: 0, y: 0 }, &Position { x: 5, y: 5 }),
+            ""
+        );
+        assert!(document.is_empty());
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn removing_the_only_row_leaves_an_empty_one() {
+        let mut document = document("ab");
+        assert_eq!(document.remove_row(0).as_deref(), Some("ab"));
+        assert_eq!(lines(&document), [""]);
+        assert_eq!(document.remove_row(1), None);
+    }
+
+    #[test]
+    fn typing_past_the_last_row_adds_one() {
+        let mut document = Document::default();
+        document.insert(&Position { x: 0, y: 1 }, 'a');
+        assert_eq!(lines(&document), ["", "a"]);
+        // Further down than right after the last row is ignored
+        document.insert(&Position { x: 0, y: 5 }, 'b');
+        document.insert_newline(&Position { x: 0, y: 5 });
+        assert_eq!(lines(&document), ["", "a"]);
+    }
+
+    #[test]
+    fn deleting_at_the_end_joins_rows_but_not_past_the_last() {
+        let mut document = document("ab\ncd");
+        document.delete(&Position { x: 2, y: 1 });
+        assert_eq!(lines(&document), ["ab", "cd"]);
+        document.delete(&Position { x: 2, y: 0 });
+        assert_eq!(lines(&document), ["abcd"]);
+        document.delete(&Position { x: 4, y: 0 });
+        assert_eq!(lines(&document), ["abcd"]);
+    }
+
+    #[test]
+    fn ranges_past_the_last_row_are_clamped() {
+        let mut document = document("ab\ncd");
+        let text = document.delete_range(&Position { x: 1, y: 0 }, &Position { x: 9, y: 9 });
+        assert_eq!(text, "b\ncd");
+        assert_eq!(lines(&document), ["a"]);
+        let text = document.delete_range(&Position { x: 0, y: 3 }, &Position { x: 1, y: 4 });
+        assert_eq!(text, "");
+        assert_eq!(lines(&document), ["a"]);
+    }
+
+    #[test]
+    fn saving_keeps_the_line_endings_read() {
+        let path = env::temp_dir().join(format!("editore-line-endings-{}", std::process::id()));
+        let file_name = path.to_str().unwrap();
+        let endings = [
+            "a\nb\n",
+            "a\nb",
+            "a\r\nb\r\n",
+            "a\r\nb",
+            "a\n\n",
+            "\n\nb",
+            "\n",
+            "\r\n",
+        ];
+        for contents in endings {
+            fs::write(&path, contents).unwrap();
+            let mut document = Document::open(file_name).unwrap();
+            document.insert(&Position { x: 0, y: 0 }, 'x');
+            document.save().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), format!("x{contents}"));
+        }
+        for contents in ["", "\n", "\r\n", "\n\n"] {
+            fs::write(&path, contents).unwrap();
+            Document::open(file_name).unwrap().save().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        }
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn a_new_document_saves_as_an_empty_file() {
+        let path = env::temp_dir().join(format!("editore-new-{}", std::process::id()));
+        fs::remove_file(&path).ok();
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        document.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_file(&path).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn swap_files_are_as_private_as_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let path = env::temp_dir().join(format!("editore-private-{}", std::process::id()));
+        let swap = path.with_file_name(format!(
+            ".{}.editore-swap",
+            path.file_name().unwrap().to_str().unwrap()
+        ));
+        for (original, expected) in [(Some(0o600), 0o600), (Some(0o640), 0o640), (None, 0o600)] {
+            fs::remove_file(&path).ok();
+            if let Some(original) = original {
+                fs::write(&path, "secret\n").unwrap();
+                fs::set_permissions(&path, fs::Permissions::from_mode(original)).unwrap();
+            }
+            let mut document = Document::open(path.to_str().unwrap()).unwrap();
+            document.insert(&Position { x: 0, y: 0 }, 'x');
+            document.write_swap().unwrap();
+            assert_eq!(mode(&swap), expected);
+            document.remove_swap();
+        }
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn swap_files_are_only_written_again_after_a_change() {
+        let path = env::temp_dir().join(format!("editore-reswap-{}", std::process::id()));
+        fs::write(&path, "a\n").unwrap();
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        let swap = document.swap_path().unwrap();
+        document.insert(&Position { x: 0, y: 0 }, 'x');
+        document.write_swap().unwrap();
+        assert_eq!(fs::read_to_string(&swap).unwrap(), "xa\n");
+        fs::write(&swap, "untouched").unwrap();
+        document.write_swap().unwrap();
+        assert_eq!(fs::read_to_string(&swap).unwrap(), "untouched");
+        document.insert(&Position { x: 0, y: 0 }, 'y');
+        document.write_swap().unwrap();
+        assert_eq!(fs::read_to_string(&swap).unwrap(), "yxa\n");
+        document.remove_swap();
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn swapping_a_large_file_copies_the_rows_not_read_yet() {
+        let path = env::temp_dir().join(format!("editore-large-swap-{}", std::process::id()));
+        let line = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcde\n";
+        let contents = line.repeat(usize::try_from(LAZY_LOAD_THRESHOLD).unwrap() / line.len() + 1);
+        fs::write(&path, &contents).unwrap();
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        document.load_rows(1);
+        document.insert(&Position { x: 0, y: 0 }, 'x');
+        document.write_swap().unwrap();
+        assert!(document.pending.is_some());
+        let swap = document.swap_path().unwrap();
+        assert!(fs::read_to_string(&swap).unwrap() == format!("x{contents}"));
+        document.remove_swap();
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn saving_ends_with_exactly_one_line_ending_when_asked() {
+        let path = env::temp_dir().join(format!("editore-final-newline-{}", std::process::id()));
+        let file_name = path.to_str().unwrap();
+        for contents in ["a", "a\n", "a\n\n\n", "a\r\n\r\n"] {
+            fs::write(&path, contents).unwrap();
+            let mut document = Document::open(file_name).unwrap();
+            document.trim_trailing_blank_rows();
+            document.add_final_line_ending();
+            document.save().unwrap();
+            let expected = if contents.contains('\r') {
+                "a\r\n"
+            } else {
+                "a\n"
+            };
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn matches_are_found_again_once_the_rows_change() {
+        let mut document = document("ab\nb");
+        let re = Regex::new("b").unwrap();
+        let (matches, complete) = document.find_all(&re);
+        assert_eq!(matches, [Position { x: 1, y: 0 }, Position { x: 0, y: 1 }]);
+        assert!(complete);
+        document.insert(&Position { x: 0, y: 0 }, 'b');
+        assert_eq!(document.find_all(&re).0.len(), 3);
+        let re = Regex::new("a").unwrap();
+        assert_eq!(document.find_all(&re).0, [Position { x: 1, y: 0 }]);
+    }
+
+    #[test]
+    fn newline_at_the_end_of_the_last_row_adds_an_empty_one() {
+        let mut document = document("  ab");
+        let indent = document.insert_newline(&Position { x: 4, y: 0 });
+        assert_eq!(indent, 2);
+        assert_eq!(lines(&document), ["  ab", "  "]);
     }
 }
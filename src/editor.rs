@@ -1,302 +1,3714 @@
+use regex::Regex;
 use std::{
+    cmp,
+    collections::hash_map::DefaultHasher,
     env,
+    ffi::OsStr,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    iter, mem,
+    ops::Range,
+    path::Path,
     time::{Duration, Instant},
 };
-use syntect::parsing::SyntaxSet;
-use syntect::{easy::HighlightLines, highlighting::ThemeSet};
-use syntect::{parsing::SyntaxReference, util::as_24_bit_terminal_escaped};
-use termion::{color, event::Key};
+use syntect::highlighting::{
+    Color, FontStyle, HighlightIterator, HighlightState, Highlighter, Style, ThemeSet,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::as_24_bit_terminal_escaped;
+use termion::{
+    color,
+    event::{Event, Key, MouseButton, MouseEvent},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+use crate::arguments::Arguments;
+use crate::command::{Command, ConflictAction};
+use crate::config::{self, Config};
+use crate::cursor_history;
+use crate::finder::Finder;
+use crate::folds::Folds;
+use crate::history::{Edit, History};
+use crate::jumps::{Jump, JumpList};
+use crate::keymap::{Action, Keymap};
+#[cfg(feature = "lsp")]
+use crate::lsp;
+use crate::recent_files;
+use crate::terminal::{self, Terminal};
 use crate::Document;
+use crate::Indent;
+use crate::Marks;
 use crate::Row;
-use crate::Terminal;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+const SCROLL_LINES: usize = 3;
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const READ_ONLY_MESSAGE: &str = "Read-only mode, use :set noreadonly to edit";
+/// How often unsaved changes are written to swap files
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+/// How often to look whether the file being edited changed on disk
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const LINE_NUMBER_FG_COLOR: color::Rgb = color::Rgb(128, 128, 128);
+const SCROLLBAR_COLOR: color::Rgb = color::Rgb(96, 96, 96);
+/// Behind the lines marking a merge conflict
+const CONFLICT_BG_COLOR: Color = Color {
+    r: 0x8b,
+    g: 0x3a,
+    b: 0x4a,
+    a: 0xff,
+};
+/// Columns kept free on the right edge for the scrollbar
+const SCROLLBAR_WIDTH: usize = 1;
+/// How many columns the minimap takes, left of the scrollbar
+const MINIMAP_WIDTH: usize = 12;
+/// How many screen columns of a row each minimap cell stands for
+const MINIMAP_CELL_WIDTH: usize = 4;
+const MINIMAP_FG_COLOR: color::Rgb = color::Rgb(128, 128, 128);
+const MINIMAP_VIEW_COLOR: color::Rgb = color::Rgb(64, 64, 64);
+/// The token starting a line comment, by syntax name
+const COMMENT_TOKENS: &[(&str, &str)] = &[
+    ("Rust", "//"),
+    ("C", "//"),
+    ("C++", "//"),
+    ("C#", "//"),
+    ("D", "//"),
+    ("Go", "//"),
+    ("Groovy", "//"),
+    ("Java", "//"),
+    ("JavaScript", "//"),
+    ("Objective-C", "//"),
+    ("Objective-C++", "//"),
+    ("PHP", "//"),
+    ("Scala", "//"),
+    ("Python", "#"),
+    ("Ruby", "#"),
+    ("Perl", "#"),
+    ("R", "#"),
+    ("Bourne Again Shell (bash)", "#"),
+    ("Makefile", "#"),
+    ("YAML", "#"),
+    ("TOML", "#"),
+    ("Haskell", "--"),
+    ("Lua", "--"),
+    ("SQL", "--"),
+    ("Erlang", "%"),
+    ("LaTeX", "%"),
+    ("TeX", "%"),
+    ("Lisp", ";"),
+    ("Clojure", ";"),
+];
 
-#[derive(Default)]
+/// The syntaxes whose blocks are between braces, indented after `{` and
+/// dedented at `}` when typing
+const BRACE_SYNTAXES: &[&str] = &[
+    "Rust",
+    "C",
+    "C++",
+    "C#",
+    "D",
+    "Go",
+    "Groovy",
+    "Java",
+    "JavaScript",
+    "Objective-C",
+    "Objective-C++",
+    "PHP",
+    "Scala",
+    "CSS",
+    "JSON",
+];
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+pub type HighlightingState = (ParseState, HighlightState);
+
 pub struct HighLightManager<'a> {
-    highlighter: HighlightLines<'a>,
+    highlighter: Highlighter<'a>,
+    ps: &'a SyntaxSet,
     ts: &'a ThemeSet,
     syntax: &'a SyntaxReference,
+    theme: String,
 }
 
 impl<'a> HighLightManager<'a> {
-    pub fn default(ps: &'a SyntaxSet, ts: &'a ThemeSet) -> Self {
-        let syntax = ps.find_syntax_by_extension("rs").unwrap();
-        let highlighter = HighlightLines::new(syntax, &ts.themes["base16-mocha.dark"]);
+    pub fn default(ps: &'a SyntaxSet, ts: &'a ThemeSet, file_name: Option<&str>) -> Self {
+        let syntax = find_syntax(ps, file_name);
+        let theme = String::from("base16-mocha.dark");
+        let highlighter = Highlighter::new(&ts.themes[&theme]);
 
         Self {
             highlighter,
+            ps,
             ts,
             syntax,
+            theme,
+        }
+    }
+
+    pub fn change_theme(&mut self, theme: &str) -> Result<(), String> {
+        let new_theme = self
+            .ts
+            .themes
+            .get(theme)
+            .ok_or_else(|| format!("Unknown theme: {theme}"))?;
+        self.highlighter = Highlighter::new(new_theme);
+        self.theme = theme.to_string();
+        Ok(())
+    }
+
+    pub fn theme(&self) -> &str {
+        &self.theme
+    }
+
+    pub fn theme_names(&self) -> Vec<&str> {
+        self.ts.themes.keys().map(String::as_str).collect()
+    }
+
+    pub fn next_theme(&self) -> &str {
+        let names = self.theme_names();
+        let current = names.iter().position(|name| *name == self.theme);
+        let next = current.map_or(0, |index| (index + 1) % names.len());
+        names[next]
+    }
+
+    /// Background for the line the cursor is on, the theme's own line
+    /// highlight if it has one or else a shade off its background
+    pub fn line_highlight(&self) -> Option<Color> {
+        let settings = &self.ts.themes.get(&self.theme)?.settings;
+        settings
+            .line_highlight
+            .or_else(|| settings.background.map(|background| shade(background, 24)))
+    }
+
+    pub fn background(&self) -> Option<Color> {
+        self.ts.themes.get(&self.theme)?.settings.background
+    }
+
+    /// The theme's colors for text that isn't highlighted
+    pub fn plain_style(&self) -> Style {
+        let settings = self.ts.themes.get(&self.theme).map(|theme| &theme.settings);
+        Style {
+            foreground: settings
+                .and_then(|settings| settings.foreground)
+                .unwrap_or(Color::WHITE),
+            background: settings
+                .and_then(|settings| settings.background)
+                .unwrap_or(Color::BLACK),
+            font_style: FontStyle::empty(),
+        }
+    }
+
+    /// A faint foreground for what isn't part of the document, like the
+    /// lines past its end
+    pub fn dim_foreground(&self) -> Option<Color> {
+        self.background().map(|background| shade(background, 80))
+    }
+
+    pub fn detect_syntax(&mut self, file_name: Option<&str>) {
+        self.syntax = find_syntax(self.ps, file_name);
+    }
+
+    /// Name of the detected syntax, or "no ft" for plain text
+    pub fn file_type(&self) -> &str {
+        if self.syntax.name == self.ps.find_syntax_plain_text().name {
+            "no ft"
+        } else {
+            &self.syntax.name
         }
     }
 
-    pub fn change_theme(&mut self, theme: &str) {
-        self.highlighter = HighlightLines::new(self.syntax, &self.ts.themes[theme]);
+    /// The token starting a line comment in the detected syntax
+    pub fn comment_token(&self) -> Option<&'static str> {
+        COMMENT_TOKENS
+            .iter()
+            .find(|(syntax, _)| *syntax == self.syntax.name)
+            .map(|(_, token)| *token)
+    }
+
+    /// Whether blocks of the detected syntax are between braces
+    pub fn indents_braces(&self) -> bool {
+        BRACE_SYNTAXES.contains(&self.syntax.name.as_str())
+    }
+
+    pub fn start_state(&self) -> HighlightingState {
+        (
+            ParseState::new(self.syntax),
+            HighlightState::new(&self.highlighter, ScopeStack::new()),
+        )
+    }
+
+    /// Tells apart lines by their text and the theme they would be drawn
+    /// in, so highlighting can be kept for lines that didn't change
+    pub fn line_key(&self, line: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.theme.hash(&mut hasher);
+        line.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn highlight_line(
+        &self,
+        line: &str,
+        state: &mut HighlightingState,
+    ) -> Vec<(Style, String)> {
+        // The default syntaxes expect lines to be terminated by a newline
+        let line = format!("{line}\n");
+        let ops = state.0.parse_line(&line, self.ps);
+        HighlightIterator::new(&mut state.1, &ops, &line, &self.highlighter)
+            .map(|(style, text)| (style, text.trim_end_matches('\n').to_string()))
+            .filter(|(_, text)| !text.is_empty())
+            .collect()
     }
 }
 
+fn find_syntax<'a>(ps: &'a SyntaxSet, file_name: Option<&str>) -> &'a SyntaxReference {
+    file_name
+        .and_then(|name| Path::new(name).extension())
+        .and_then(OsStr::to_str)
+        .and_then(|extension| ps.find_syntax_by_extension(extension))
+        .unwrap_or_else(|| ps.find_syntax_plain_text())
+}
+
+#[derive(PartialEq)]
+enum Severity {
+    /// Cleared once the message timeout runs out
+    Info,
+    /// Kept until the next key, so it isn't missed
+    Error,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
+    severity: Severity,
 }
 impl StatusMessage {
+    /// A message for the message bar, an error when it starts with `ERR`
     fn from(message: String) -> Self {
         Self {
             time: Instant::now(),
+            severity: if message.starts_with("ERR") {
+                Severity::Error
+            } else {
+                Severity::Info
+            },
             text: message,
         }
     }
+
+    fn is_shown(&self, timeout: Duration) -> bool {
+        self.severity == Severity::Error || self.time.elapsed() < timeout
+    }
+}
+
+/// What was last cut or copied, for Ctrl-V to paste
+#[derive(Clone)]
+enum Clipboard {
+    /// A whole line, pasted above the cursor
+    Line(String),
+    /// Part of the document, pasted at the cursor
+    Text(String),
+}
+
+/// A document along with the editing state kept for it while another one is
+/// being edited
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    history: History,
+    folds: Folds,
+    read_only: bool,
 }
 
+/// The editor itself, editing one or more documents in the terminal
+#[allow(clippy::struct_excessive_bools)]
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
     cursor_position: Position,
     document: Document,
     offset: Position,
-    ss: SyntaxSet,
     status_message: StatusMessage,
+    show_line_numbers: bool,
+    soft_wrap: bool,
+    /// Whether a prompt is waiting for input in the message bar
+    prompting: bool,
+    /// Ignore every key that would change the current document, kept for
+    /// each buffer
+    read_only: bool,
+    /// Typed characters replace the one under the cursor instead of being
+    /// inserted before it, toggled with Insert
+    overwrite: bool,
+    tab_stop: usize,
+    insert_spaces: bool,
+    history: History,
+    folds: Folds,
+    /// Every open buffer, the current one being only a placeholder while its
+    /// state lives in the fields above
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    /// Where the selection started, the cursor being its other end
+    selection_anchor: Option<Position>,
+    /// Where the cursor was before searches, page moves and the like took
+    /// it elsewhere
+    jumps: JumpList,
+    /// The cursors added with Ctrl-D besides `cursor_position`, typing
+    /// and deleting at all of them at once
+    cursors: Vec<Position>,
+    clipboard: Option<Clipboard>,
+    /// The fuzzy file finder, while it is open
+    finder: Option<Finder>,
+    /// The count typed with Alt and digits to repeat the next movement
+    pending_count: Option<usize>,
+    /// Whether the keybindings are shown over the document, until the next
+    /// key is pressed
+    show_help: bool,
+    /// Which keys run which actions, from the defaults and the config file
+    keymap: Keymap,
+    /// What the search being typed matches, highlighted on screen
+    search_pattern: Option<Regex>,
+    /// The last search that was accepted, repeated with Alt-n and Alt-N
+    last_search: Option<Regex>,
+    /// How many closing brackets were inserted right after the cursor while
+    /// typing, as long as it stays where it is
+    auto_closed: Option<(Position, usize)>,
+    /// Where Enter left the cursor after continuing a comment marker or list
+    /// bullet, with the document revision then, so that Enter right there
+    /// again removes the marker
+    continued: Option<(Position, usize)>,
+    /// The screen column vertical movement aims for, kept while the cursor
+    /// stays where the last vertical move left it
+    desired_column: Option<(usize, Position)>,
+    /// When the swap files were last written
+    swapped_at: Instant,
+    /// When the file being edited was last checked for outside changes
+    disk_checked_at: Instant,
+    #[cfg(feature = "lsp")]
+    lsp: lsp::Session,
+    quit_times: u8,
+    config: Config,
 }
 
 impl Editor {
-    pub fn run(&mut self, h: &mut HighLightManager) {
+    /// Reads and handles keys until the user quits, clearing the screen
+    /// before returning an error the editor can't recover from
+    pub fn run(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        if let Err(error) = h.change_theme(&self.config.theme) {
+            self.status_message = StatusMessage::from(format!("WARNING: {error}"));
+        }
+        let result = self.check_opened_files(h).and_then(|()| self.event_loop(h));
+        if result.is_err() {
+            Terminal::clear_screen(&mut self.terminal.output())?;
+        }
+        result
+    }
+
+    fn event_loop(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
         loop {
-            if let Err(error) = self.refresh_screen(h) {
-                die(error);
-            }
+            self.render_frame(&mut self.terminal.output(), h)?;
             if self.should_quit {
-                break;
+                return Ok(());
+            }
+            self.process_keypress(h)?;
+            #[cfg(feature = "lsp")]
+            self.lsp.sync(h.file_type(), &mut self.document);
+            if self.swapped_at.elapsed() >= SWAP_INTERVAL {
+                self.write_swaps();
+            }
+            if self.disk_checked_at.elapsed() >= DISK_CHECK_INTERVAL {
+                self.check_disk();
+            }
+        }
+    }
+
+    /// Writes the unsaved changes of every buffer to its swap file, which
+    /// `write_swap` skips for buffers unchanged since their last swap
+    fn write_swaps(&mut self) {
+        self.swapped_at = Instant::now();
+        let documents = iter::once(&mut self.document)
+            .chain(self.buffers.iter_mut().map(|buffer| &mut buffer.document));
+        for document in documents.filter(|document| document.is_dirty()) {
+            if let Err(error) = document.write_swap() {
+                self.status_message =
+                    StatusMessage::from(format!("WARNING: Could not write swap file: {error}"));
             }
-            if let Err(error) = self.process_keypress(h) {
-                die(error);
+        }
+    }
+
+    /// Warns when something else changed the file being edited
+    fn check_disk(&mut self) {
+        self.disk_checked_at = Instant::now();
+        if self.document.changed_on_disk() {
+            self.status_message = StatusMessage::from(format!(
+                "WARNING: {} changed on disk, :e! reloads it",
+                self.file_name().unwrap_or_default()
+            ));
+        }
+    }
+
+    fn remove_swaps(&self) {
+        self.document.remove_swap();
+        for buffer in &self.buffers {
+            buffer.document.remove_swap();
+        }
+    }
+
+    /// Asks about the files opened on the command line that look binary or
+    /// have a swap file left behind
+    fn check_opened_files(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let current = self.current_buffer;
+        for index in 0..self.buffers.len() {
+            let document = if index == current {
+                &self.document
+            } else {
+                &self.buffers[index].document
+            };
+            if document.is_binary() || document.has_swap() {
+                self.switch_buffer(h, index);
+                self.offer_read_only(h)?;
+                self.offer_recovery(h)?;
             }
         }
+        self.switch_buffer(h, current);
+        Ok(())
+    }
+
+    /// Asks whether to switch to read-only mode if the current document looks
+    /// binary, since saving it would mangle what isn't valid text
+    fn offer_read_only(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        if !self.document.is_binary() || self.read_only {
+            return Ok(());
+        }
+        let file_name = self.file_name().unwrap_or_default().to_string();
+        if self.confirm(
+            &format!("{file_name} looks like a binary file, view it read-only? (y/n): "),
+            h,
+        )? {
+            self.read_only = true;
+        } else {
+            self.status_message =
+                StatusMessage::from(format!("WARNING: Saving {file_name} may corrupt it"));
+        }
+        Ok(())
+    }
+
+    /// Asks whether to replace the current document with its swap file, the
+    /// swap file being dropped when the answer is no
+    fn offer_recovery(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        if !self.document.has_swap() {
+            return Ok(());
+        }
+        let file_name = self.file_name().unwrap_or_default().to_string();
+        if self.confirm(
+            &format!("Found unsaved changes to {file_name}, recover them? (y/n): "),
+            h,
+        )? {
+            self.status_message = match self.document.recover() {
+                Ok(()) => {
+                    self.keep_cursor_in_document();
+                    StatusMessage::from(format!("Recovered unsaved changes to {file_name}"))
+                }
+                Err(error) => StatusMessage::from(format!("ERR: Could not recover: {error}")),
+            };
+        } else {
+            self.document.remove_swap();
+        }
+        Ok(())
     }
 
-    fn refresh_screen(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+    /// Draws the document, status bar and message bar into `out`, and places
+    /// the cursor
+    pub fn render_frame(
+        &mut self,
+        out: &mut dyn Write,
+        h: &mut HighLightManager,
+    ) -> Result<(), std::io::Error> {
+        if self.terminal.update_size()? {
+            Terminal::clear_screen(out)?;
+            self.scroll();
+        }
+        Terminal::cursor_hide(out)?;
+        Terminal::cursor_position(out, &Position::default())?;
 
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Goodbye.\r");
+            Terminal::clear_screen(out)?;
+            writeln!(out, "Goodbye.\r")?;
         } else {
-            self.draw_rows(h);
-            self.draw_status_bar();
-            self.draw_message_bar();
+            let height = self.terminal.size().height as usize;
+            self.document.load_rows(cmp::max(
+                self.offset.y.saturating_add(height),
+                self.cursor_position.y.saturating_add(1),
+            ));
+            self.document
+                .highlight(h, self.offset.y.saturating_add(height));
+            self.draw_rows(out, h)?;
+            self.draw_status_bar(out, h)?;
+            self.draw_message_bar(out)?;
 
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+            let Position { x, y } = self.cursor_screen_position();
+            Terminal::cursor_position(
+                out,
+                &Position {
+                    x: x.saturating_add(self.gutter_width()),
+                    y,
+                },
+            )?;
         }
 
-        Terminal::cursor_show();
-        Terminal::flush()
+        Terminal::cursor_show(out)?;
+        Terminal::flush(out)
     }
 
-    fn draw_status_bar(&self) {
-        let mut status;
+    fn draw_status_bar(
+        &self,
+        out: &mut dyn Write,
+        h: &HighLightManager,
+    ) -> Result<(), std::io::Error> {
         let width = self.terminal.size().width as usize;
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
+        let status = match &self.config.status_format {
+            Some(format) => self.formatted_status(format, h, width),
+            None => self.default_status(h, width),
+        };
+        self.set_status_colors(out)?;
+        writeln!(out, "{status}\r")?;
+        Terminal::reset_fg_color(out)?;
+        Terminal::reset_bg_color(out)
+    }
+
+    fn set_status_colors(&self, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        let config::Rgb(r, g, b) = self.config.status_bg_color;
+        Terminal::set_bg_color(out, color::Rgb(r, g, b))?;
+        let config::Rgb(r, g, b) = self.config.status_fg_color;
+        Terminal::set_fg_color(out, color::Rgb(r, g, b))
+    }
+
+    /// The status bar laid out by `status_format`, the left part cut short
+    /// when the line is too narrow
+    fn formatted_status(&self, format: &str, h: &HighLightManager, width: usize) -> String {
+        let (left, right) = format.split_once("{>}").unwrap_or((format, ""));
+        let right = truncate_to_width(&self.expand_status(right, h), width);
+        let left_width = width.saturating_sub(right.width());
+        let left = truncate_to_width(&self.expand_status(left, h), left_width);
+        let padding = left_width.saturating_sub(left.width());
+        format!("{left}{}{right}", " ".repeat(padding))
+    }
+
+    /// Replaces the `{name}` fields in `format` by what they name, leaving
+    /// unknown ones as they are
+    fn expand_status(&self, format: &str, h: &HighLightManager) -> String {
+        let mut expanded = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            let Some(length) = rest[start..].find('}') else {
+                // An unmatched brace is shown as it is
+                rest = &rest[start..];
+                break;
+            };
+            let field = &rest[start + 1..start + length];
+            let value = match field {
+                "file" => self
+                    .document
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| "[No Name]".to_string()),
+                "lines" => self.document.len().to_string(),
+                "line" => self.cursor_position.y.saturating_add(1).to_string(),
+                "col" => self.cursor_position.x.saturating_add(1).to_string(),
+                "ft" => self.file_type(h),
+                "encoding" => self.document.encoding().to_string(),
+                "mod" if self.document.is_dirty() => "(modified)".to_string(),
+                "ro" if self.read_only => "[RO]".to_string(),
+                "ovr" if self.overwrite => "[OVR]".to_string(),
+                "buffer" if self.buffers.len() > 1 => {
+                    format!("{}/{}", self.current_buffer + 1, self.buffers.len())
+                }
+                "undo" => match (self.history.undo_count(), self.history.redo_count()) {
+                    (0, 0) => String::new(),
+                    (undo, 0) => format!("undo {undo}"),
+                    (undo, redo) => format!("undo {undo} redo {redo}"),
+                },
+                "mod" | "ro" | "ovr" | "buffer" => String::new(),
+                _ => rest[start..=start + length].to_string(),
+            };
+            expanded.push_str(&value);
+            rest = &rest[start + length + 1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// The file type shown in the status bar, marked when highlighting is off
+    fn file_type(&self, h: &HighLightManager) -> String {
+        if self.document.is_plain() {
+            format!("{} (plain)", h.file_type())
+        } else {
+            h.file_type().to_string()
+        }
+    }
+
+    fn default_status(&self, h: &HighLightManager, width: usize) -> String {
+        let mut status;
+        let file_name = self.document.file_name.as_deref().map_or_else(
+            || "[No Name]".to_string(),
+            |name| truncate_to_width(name, 20),
+        );
+        let modified_indicator = if self.document.is_dirty() {
+            " (modified)"
+        } else {
+            ""
+        };
+        let lines = self.document.len();
+        let plural = if lines == 1 { "" } else { "s" };
+        let read_only_indicator = if self.read_only { " [RO]" } else { "" };
+        let overwrite_indicator = if self.overwrite { " [OVR]" } else { "" };
+        status = format!(
+            "{file_name}{read_only_indicator}{overwrite_indicator} - {lines} line{plural}{modified_indicator}"
+        );
+        if self.buffers.len() > 1 {
+            status = format!(
+                "[{}/{}] {status}",
+                self.current_buffer + 1,
+                self.buffers.len()
+            );
         }
-        status = format!("{} - {} lines", file_name, self.document.len());
         let line_indicator = format!(
-            "{}/{} ",
+            "{}/{} col {} ",
             self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            self.document.len(),
+            self.cursor_position.x.saturating_add(1)
         );
-        let len = status.len() + line_indicator.len();
+        let file_type = format!("{} | {} | ", self.document.encoding(), self.file_type(h));
 
-        if width > len {
-            status.push_str(&" ".repeat(width - len));
-        }
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
+        let undo_indicator = match (self.history.undo_count(), self.history.redo_count()) {
+            (0, 0) => String::new(),
+            (undo, 0) => format!("undo {undo} | "),
+            (undo, redo) => format!("undo {undo} redo {redo} | "),
+        };
 
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        // When space runs out the file name goes first, then the undo steps
+        // and the file type, and the cursor position is kept for as long as
+        // it fits
+        let mut indicators = line_indicator;
+        if file_type.width() + indicators.width() <= width {
+            indicators = format!("{file_type}{indicators}");
+        }
+        if undo_indicator.width() + indicators.width() <= width {
+            indicators = format!("{undo_indicator}{indicators}");
+        }
+        let status_width = width.saturating_sub(indicators.width());
+        status = truncate_to_width(&status, status_width);
+        let padding = status_width.saturating_sub(status.width());
+        truncate_to_width(
+            &format!("{status}{}{indicators}", " ".repeat(padding)),
+            width,
+        )
     }
 
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+    fn draw_message_bar(&self, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        Terminal::clear_current_line(out)?;
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+        let width = self.terminal.size().width as usize;
+        if message.is_shown(Duration::from_secs(self.config.message_timeout)) {
+            let text = truncate_to_width(&message.text, width);
+            write!(out, "{text}")?;
+            return Ok(());
+        }
+        // With nothing else to say, tell what is wrong with the cursor's line
+        #[cfg(feature = "lsp")]
+        if let Some(diagnostic) = self
+            .lsp
+            .diagnostic(self.file_name(), self.cursor_position.y)
+        {
+            write!(out, "{}", truncate_to_width(&diagnostic.message, width))?;
+        }
+        Ok(())
+    }
+
+    /// Handles `keys` as if they were typed, a prompt opened by one of them
+    /// taking the keys after it. A prompt still open once they run out is
+    /// cancelled.
+    pub fn feed_keys(
+        &mut self,
+        h: &mut HighLightManager,
+        keys: &[Key],
+    ) -> Result<(), std::io::Error> {
+        self.terminal.queue_keys(keys);
+        while self.terminal.has_queued_keys() && !self.should_quit {
+            self.process_keypress(h)?;
+        }
+        Ok(())
+    }
+
+    /// The text of the frame drawn next, the rows followed by the status
+    /// and message bars, without colors
+    pub fn snapshot(&mut self, h: &mut HighLightManager) -> Result<String, std::io::Error> {
+        let mut out = Vec::new();
+        self.render_frame(&mut out, h)?;
+        let size = self.terminal.size();
+        Ok(terminal::screen_text(
+            &String::from_utf8_lossy(&out),
+            usize::from(size.width),
+            usize::from(size.height) + 2,
+        ))
+    }
+
+    /// Waits a little for the next event, handling those that aren't keys
+    /// for the document: mouse events, the key closing the help and editing
+    /// keys in read-only mode
+    fn read_key(&mut self) -> Result<Option<Key>, std::io::Error> {
+        let key = match self.terminal.read_event_timeout(REFRESH_INTERVAL)? {
+            Some(Event::Key(_)) if self.show_help => {
+                self.show_help = false;
+                return Ok(None);
+            }
+            Some(Event::Key(key)) => {
+                if self.status_message.severity == Severity::Error {
+                    self.status_message = StatusMessage::from(String::new());
+                }
+                key
+            }
+            Some(Event::Mouse(mouse_event)) => {
+                self.process_mouse(mouse_event);
+                return Ok(None);
+            }
+            Some(Event::Unsupported(bytes)) if bytes == terminal::PASTE_START => {
+                let text = self.terminal.read_paste()?;
+                self.paste_text(&text);
+                return Ok(None);
+            }
+            Some(Event::Unsupported(_)) | None => return Ok(None),
+        };
+        if self.document.is_directory() && self.is_editing_key(key) && key != Key::Char('\n') {
+            self.status_message =
+                StatusMessage::from("Directory listing, press Enter to open an entry".to_string());
+            return Ok(None);
+        }
+        if self.read_only && self.is_editing_key(key) && !self.document.is_directory() {
+            self.status_message = StatusMessage::from(READ_ONLY_MESSAGE.to_string());
+            return Ok(None);
         }
+        Ok(Some(key))
     }
 
     fn process_keypress(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+        let old_position = self.cursor_position;
+        let old_buffer = self.current_buffer;
+        let Some(pressed_key) = self.read_key()? else {
+            return Ok(());
+        };
+        if !self.cursors.is_empty() && self.edit_at_cursors(pressed_key) {
+            self.scroll();
+            return Ok(());
+        }
+
+        match self.keymap.action(pressed_key) {
+            Some(Action::Quit) => {
+                self.quit();
+                if !self.should_quit {
+                    return Ok(());
+                }
+            }
+            Some(action) => self.run_action(h, action)?,
+            None => self.process_other_key(h, pressed_key)?,
+        }
+        if !self.keeps_selection(pressed_key) {
+            self.selection_anchor = None;
+        }
+        self.remember_jump(pressed_key, old_buffer, old_position);
+        if !matches!(pressed_key, Key::Alt('0'..='9')) && self.pending_count.take().is_some() {
+            self.status_message = StatusMessage::from(String::new());
+        }
+
+        self.scroll();
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
+            self.status_message = StatusMessage::from(String::new());
+        }
+        if self.cursor_position != old_position && self.on_unmatched_bracket() {
+            self.status_message = StatusMessage::from("No matching bracket".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Runs `action` for the key bound to it, except quitting, which
+    /// `process_keypress` handles
+    fn run_action(
+        &mut self,
+        h: &mut HighLightManager,
+        action: Action,
+    ) -> Result<(), std::io::Error> {
+        match action {
+            Action::Help => self.show_help = true,
+            Action::Save => self.save(h)?,
+            Action::Open => self.open(h)?,
+            Action::FindFile => self.find_file(h)?,
+            Action::OpenRecent => self.open_recent(h)?,
+            Action::Command => self.command(h)?,
+            Action::NextBuffer => self.next_buffer(h),
+            Action::ToggleLineNumbers => self.show_line_numbers = !self.show_line_numbers,
+            Action::ToggleSoftWrap => self.toggle_soft_wrap(),
+            Action::NextTheme => self.next_theme(h),
+            Action::PickTheme => self.pick_theme(h)?,
+            Action::ToggleFold => self.toggle_fold(),
+            Action::ToggleOverwrite => self.overwrite = !self.overwrite,
+            Action::NextConflict => self.jump_to_conflict(SearchDirection::Forward),
+            Action::PreviousConflict => self.jump_to_conflict(SearchDirection::Backward),
+            Action::GoToLine => self.go_to_line(h)?,
+            Action::Find => self.search(h)?,
+            Action::NextMatch => self.repeat_search(SearchDirection::Forward),
+            Action::PreviousMatch => self.repeat_search(SearchDirection::Backward),
+            Action::Replace => self.replace(h)?,
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::Cut => self.cut(),
+            Action::Copy => self.copy(),
+            Action::Paste => self.paste(),
+            Action::DuplicateLine => self.duplicate_line(),
+            Action::AddCursor => self.add_cursor(),
+            Action::JumpBack => self.jump(h, SearchDirection::Backward),
+            Action::JumpForward => self.jump(h, SearchDirection::Forward),
+            Action::HalfPageUp => self.scroll_half_page(SearchDirection::Backward),
+            Action::HalfPageDown => self.scroll_half_page(SearchDirection::Forward),
+            Action::MoveLineUp => self.move_line(SearchDirection::Backward),
+            Action::MoveLineDown => self.move_line(SearchDirection::Forward),
+            Action::ToggleComment => self.toggle_comment(h),
+            Action::Quit => self.quit(),
+        }
+        Ok(())
+    }
 
+    /// Handles a key no action is bound to, typing, deleting and moving
+    fn process_other_key(
+        &mut self,
+        h: &mut HighLightManager,
+        pressed_key: Key,
+    ) -> Result<(), std::io::Error> {
         match pressed_key {
-            Key::Ctrl('q') => self.should_quit = true,
-            Key::Ctrl('t') => h.change_theme("InspiredGitHub"),
+            Key::Delete | Key::Backspace if self.selection().is_some() => {
+                self.delete_selection();
+            }
+            Key::Delete => self.delete(),
+            Key::Backspace => self.backspace(),
+            Key::Char('\n') if self.document.is_directory() => self.open_entry(h)?,
+            Key::Char('\n') => self.insert_newline(h),
+            Key::Char('\t') if self.selection().is_some_and(|(start, end)| start.y < end.y) => {
+                self.shift_lines(false);
+            }
+            Key::BackTab => self.shift_lines(true),
+            Key::Char('\t') if self.insert_spaces => self.insert_soft_tab(),
+            Key::Char(c) => self.type_char(h, c),
             Key::Up
             | Key::Down
             | Key::Left
             | Key::Right
+            | Key::CtrlLeft
+            | Key::CtrlRight
             | Key::PageUp
             | Key::PageDown
             | Key::Home
-            | Key::End => self.move_cursor(pressed_key),
+            | Key::End
+            | Key::CtrlHome
+            | Key::CtrlEnd
+            | Key::CtrlUp
+            | Key::CtrlDown => self.move_cursor_repeatedly(pressed_key),
+            // A count typed with Alt and digits repeats the next movement,
+            // Alt-0 on its own going to the start of the line like Home
+            Key::Alt('0') if self.pending_count.is_none() => self.move_cursor(Key::Home),
+            Key::Alt(digit @ '0'..='9') => self.push_count_digit(digit),
+            Key::ShiftLeft | Key::ShiftRight | Key::ShiftUp | Key::ShiftDown => {
+                self.extend_selection(pressed_key);
+            }
             _ => (),
         }
-
-        self.scroll();
-
         Ok(())
     }
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-
-        if x < self.offset.x {
-            self.offset.x = x;
-        } else if x >= self.offset.x.saturating_add(width) {
-            self.offset.x = x.saturating_sub(width).saturating_add(1);
+    /// Adds where the cursor was before `key` to the jump list if `key` took
+    /// it to another buffer or far away in this one
+    fn remember_jump(&mut self, key: Key, buffer: usize, position: Position) {
+        let jumped = self.current_buffer != buffer
+            || (self.is_jump_key(key) && self.cursor_position.y != position.y);
+        let action = self.keymap.action(key);
+        if jumped && !matches!(action, Some(Action::JumpBack | Action::JumpForward)) {
+            self.jumps.push(Jump { buffer, position });
         }
+    }
 
-        if y < self.offset.y {
-            self.offset.y = y;
-        } else if y >= self.offset.y.saturating_add(height) {
-            self.offset.y = y.saturating_sub(height).saturating_add(1);
+    /// Whether the selection stays after `key`, which is when it extends
+    /// it or works on the selected lines
+    fn keeps_selection(&self, key: Key) -> bool {
+        if let Some(action) = self.keymap.action(key) {
+            return action == Action::ToggleComment;
+        }
+        match key {
+            Key::ShiftLeft | Key::ShiftRight | Key::ShiftUp | Key::ShiftDown | Key::BackTab => true,
+            Key::Char('\t') => self.selection().is_some(),
+            _ => false,
         }
     }
 
-    fn move_cursor(&mut self, key: Key) {
-        let Position { mut y, mut x } = self.cursor_position;
+    /// Whether `key` may take the cursor far enough for where it was to be
+    /// remembered in the jump list
+    fn is_jump_key(&self, key: Key) -> bool {
+        self.keymap
+            .action(key)
+            .map_or_else(|| is_jump_key(key), Action::jumps)
+    }
 
-        let mut width = if let Some(row) = self.document.row(y) {
-            row.len()
-        } else {
-            0
+    /// Whether `key` changes the document, which read-only mode refuses
+    fn is_editing_key(&self, key: Key) -> bool {
+        self.keymap
+            .action(key)
+            .map_or_else(|| is_editing_key(key), Action::edits)
+    }
+
+    /// Adds a cursor at the next occurrence of the word under the cursor,
+    /// at the same place within it, wrapping around the end of the document
+    fn add_cursor(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let Some((word, text)) = self.document.row(y).and_then(|row| {
+            let word = row.word_at(x)?;
+            let text = row.slice(word.start, word.end);
+            Some((word, text))
+        }) else {
+            self.status_message = StatusMessage::from("No word under the cursor".to_string());
+            return;
         };
-        let height = self.document.len();
+        let Ok(re) = Regex::new(&format!(r"\b{}\b", regex::escape(&text))) else {
+            return;
+        };
+        let offset = x - word.start;
+        let last = self.cursors.last().copied().unwrap_or(self.cursor_position);
+        let after = Position {
+            x: last.x - offset + word.len(),
+            y: last.y,
+        };
+        let found = self
+            .document
+            .find_regex(&re, &after, SearchDirection::Forward)
+            .or_else(|| {
+                self.document
+                    .find_regex(&re, &Position::default(), SearchDirection::Forward)
+            })
+            .map(|at| Position {
+                x: at.x + offset,
+                y: at.y,
+            })
+            .filter(|at| *at != self.cursor_position && !self.cursors.contains(at));
+        match found {
+            Some(at) => {
+                self.cursors.push(at);
+                self.status_message =
+                    StatusMessage::from(format!("{} cursors", self.cursors.len() + 1));
+            }
+            None => {
+                self.status_message = StatusMessage::from(format!("No more occurrences of {text}"));
+            }
+        }
+    }
 
-        match key {
-            Key::Up => y = y.saturating_sub(1),
-            Key::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
+    /// Types or deletes at every cursor when there are several, returning
+    /// whether `key` was handled. Any other key but the one adding a cursor
+    /// leaves a single cursor again.
+    fn edit_at_cursors(&mut self, key: Key) -> bool {
+        if !matches!(key, Key::Char(c) if c != '\n') && !matches!(key, Key::Backspace | Key::Delete)
+        {
+            if self.keymap.action(key) != Some(Action::AddCursor) {
+                self.cursors.clear();
             }
-            Key::Left => x = x.saturating_sub(1),
-            Key::Right => {
-                if x < width {
-                    x = x.saturating_add(1);
+            return false;
+        }
+        let mut cursors: Vec<Position> = iter::once(self.cursor_position)
+            .chain(self.cursors.iter().copied())
+            .collect();
+        cursors.sort_by_key(|at| (at.y, at.x));
+        cursors.dedup();
+        let primary = cursors
+            .iter()
+            .position(|at| *at == self.cursor_position)
+            .unwrap_or(0);
+
+        // From the last cursor back, so that edits leave the positions of
+        // the cursors before them alone
+        let mut edits = Vec::new();
+        let mut edited = vec![false; cursors.len()];
+        for (index, at) in cursors.iter().enumerate().rev() {
+            let len = self.document.row(at.y).map_or(0, Row::len);
+            let edit = match key {
+                Key::Char(c) => {
+                    self.document.insert(at, c);
+                    Edit::Insert {
+                        at: *at,
+                        text: c.to_string(),
+                    }
                 }
-            }
-            // TODO: move page by page here and not to the beginning
-            Key::PageUp => y = 0,
-            Key::PageDown => y = height,
-            Key::Home => x = 0,
-            Key::End => x = width,
-            _ => (),
+                Key::Backspace if at.x > 0 => {
+                    let at = Position {
+                        x: at.x - 1,
+                        y: at.y,
+                    };
+                    let text = self.document.row(at.y).and_then(|row| row.grapheme(at.x));
+                    let text = text.unwrap_or_default().to_string();
+                    self.document.delete(&at);
+                    Edit::Delete { at, text }
+                }
+                Key::Delete if at.x < len => {
+                    let text = self.document.row(at.y).and_then(|row| row.grapheme(at.x));
+                    let text = text.unwrap_or_default().to_string();
+                    self.document.delete(at);
+                    Edit::Delete { at: *at, text }
+                }
+                _ => continue,
+            };
+            edits.push(edit);
+            edited[index] = true;
         }
 
-        width = if let Some(row) = self.document.row(y) {
-            row.len()
-        } else {
-            0
-        };
+        // Each cursor moves with its own edit and those before it on its row
+        let mut moved = Vec::with_capacity(cursors.len());
+        for (index, at) in cursors.iter().enumerate() {
+            let before = (0..index)
+                .filter(|other| edited[*other] && cursors[*other].y == at.y)
+                .count();
+            let own = usize::from(edited[index] && key != Key::Delete);
+            let x = match key {
+                Key::Char(_) => at.x + before + own,
+                _ => at.x.saturating_sub(before + own),
+            };
+            moved.push(Position { x, y: at.y });
+        }
+        self.cursor_position = moved[primary];
+        moved.dedup();
+        self.cursors = moved
+            .into_iter()
+            .filter(|at| *at != self.cursor_position)
+            .collect();
+        if !edits.is_empty() {
+            self.history.record(Edit::Group(edits));
+        }
+        self.auto_closed = None;
+        self.selection_anchor = None;
+        true
+    }
 
-        if x > width {
-            x = width;
+    /// Inserts spaces up to the next tab stop
+    fn insert_soft_tab(&mut self) {
+        let column = self.cursor_render_x();
+        for _ in 0..self.tab_stop - column % self.tab_stop {
+            self.insert_char(' ');
         }
+    }
 
-        self.cursor_position = Position { x, y }
+    fn next_buffer(&mut self, h: &mut HighLightManager) {
+        let next = (self.current_buffer + 1) % self.buffers.len();
+        self.switch_buffer(h, next);
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("Hector editor -- version {}\r", VERSION);
+    fn next_theme(&mut self, h: &mut HighLightManager) {
+        let theme = h.next_theme().to_string();
+        self.change_theme(h, &theme);
+    }
 
-        let width = self.terminal.size().width as usize;
-        let len = welcome_message.len();
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
+    fn push_count_digit(&mut self, digit: char) {
+        let digit = digit.to_digit(10).map_or(0, |digit| digit as usize);
+        let count = self
+            .pending_count
+            .unwrap_or(0)
+            .saturating_mul(10)
+            .saturating_add(digit);
+        self.pending_count = Some(count);
+        self.status_message = StatusMessage::from(format!("Repeat: {count}"));
+    }
 
-        welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
+    /// Moves the cursor as many times as the pending count says, stopping
+    /// early once it no longer moves
+    fn move_cursor_repeatedly(&mut self, key: Key) {
+        for _ in 0..self.pending_count.unwrap_or(1) {
+            let before = self.cursor_position;
+            match key {
+                Key::CtrlUp => self.jump_to_block(SearchDirection::Backward),
+                Key::CtrlDown => self.jump_to_block(SearchDirection::Forward),
+                _ => self.move_cursor(key),
+            }
+            if self.cursor_position == before {
+                break;
+            }
+        }
+    }
 
-        println!("{}\r", welcome_message);
+    /// Opens the fold at the cursor, or folds the block the cursor is on
+    fn toggle_fold(&mut self) {
+        let y = self.cursor_position.y;
+        let len = self.document.len();
+        if self.folds.unfold(y, len) {
+            return;
+        }
+        let Some((start, hidden)) = self.document.block_around(y, self.tab_stop) else {
+            self.status_message = StatusMessage::from("Nothing to fold".to_string());
+            return;
+        };
+        self.folds.fold(hidden, len);
+        let width = self.document.row(start).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: cmp::min(self.cursor_position.x, width),
+            y: start,
+        };
     }
 
-    pub fn draw_row(&self, row: &Row, h: &mut HighLightManager) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x + width;
-        let row = row.render(start, end);
-        let ranges = h.highlighter.highlight(&row, &self.ss);
-        let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+    fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.offset.x = 0;
+    }
 
-        println!("{}\r", escaped)
+    fn undo(&mut self) {
+        if let Some(position) = self.history.undo(&mut self.document) {
+            self.cursor_position = position;
+        }
     }
 
-    fn draw_rows(&self, h: &mut HighLightManager) {
-        let height = self.terminal.size().height;
+    fn redo(&mut self) {
+        if let Some(position) = self.history.redo(&mut self.document) {
+            self.cursor_position = position;
+        }
+    }
 
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(terminal_row as usize + self.offset.y) {
-                self.draw_row(row, h);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+    /// Quits unless there are unsaved changes, in which case Ctrl-Q has to
+    /// be pressed `quit_times` times
+    fn quit(&mut self) {
+        let dirty = self.dirty_buffers();
+        if self.quit_times > 1 && dirty > 0 {
+            self.quit_times -= 1;
+            let files = if dirty == 1 {
+                "File has".to_string()
             } else {
-                println!("~\r");
-            }
+                format!("{dirty} files have")
+            };
+            self.status_message = StatusMessage::from(format!(
+                "WARNING! {files} unsaved changes. Press Ctrl-Q {} more times to quit.",
+                self.quit_times
+            ));
+            return;
         }
+        self.remember_files();
+        self.remove_swaps();
+        self.should_quit = true;
     }
 
-    pub fn default(args: Vec<String>) -> Self {
-        let ps = SyntaxSet::load_defaults_newlines();
-        let mut initial_status = String::from("HELP: Ctrl-Q = quit");
+    /// Stores the cursor position of every open file for the next time it
+    /// is opened, and puts the files at the top of the recent ones with the
+    /// current one first, on a best effort basis
+    fn remember_files(&self) {
+        let mut positions: Vec<(&str, Position)> = self
+            .buffers
+            .iter()
+            .filter_map(|buffer| {
+                let file_name = buffer.document.file_name.as_deref()?;
+                Some((file_name, buffer.cursor_position))
+            })
+            .collect();
+        if let Some(file_name) = self.file_name() {
+            positions.push((file_name, self.cursor_position));
+        }
+        cursor_history::store(&positions).ok();
+        let file_names: Vec<&str> = positions.iter().map(|(file_name, _)| *file_name).collect();
+        recent_files::add(&file_names).ok();
+    }
 
-        let document = if args.len() > 1 {
-            let file_name = &args[1];
-            let doc = Document::open(&file_name);
-            if doc.is_ok() {
-                doc.unwrap()
-            } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+    fn process_mouse(&mut self, mouse_event: MouseEvent) {
+        let height = self.terminal.size().height as usize;
+        match mouse_event {
+            MouseEvent::Press(MouseButton::Left, column, line) => {
+                let line = usize::from(line.saturating_sub(1));
+                let column = usize::from(column.saturating_sub(1));
+                let minimap_start = self.gutter_width() + self.text_width();
+                let on_minimap =
+                    (minimap_start..minimap_start + self.minimap_width()).contains(&column);
+                if line < height && on_minimap {
+                    self.scroll_to_minimap_line(line);
+                } else if line < height {
+                    self.cursor_position = self.position_from_screen(column, line);
+                    self.selection_anchor = None;
+                    self.scroll();
+                }
+            }
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => {
+                self.offset.y = self.offset.y.saturating_sub(SCROLL_LINES);
+                self.keep_cursor_on_screen();
             }
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => {
+                self.offset.y = cmp::min(
+                    self.offset.y.saturating_add(SCROLL_LINES),
+                    self.document.len().saturating_sub(1),
+                );
+                self.keep_cursor_on_screen();
+            }
+            _ => (),
+        }
+    }
+
+    /// Centers the document rows minimap line `line` stands for on screen
+    fn scroll_to_minimap_line(&mut self, line: usize) {
+        let height = self.terminal.size().height as usize;
+        let scale = self.minimap_scale(height);
+        let middle = line * scale + scale / 2;
+        self.offset.y = cmp::min(
+            middle.saturating_sub(height / 2),
+            self.document.len().saturating_sub(1),
+        );
+        self.keep_cursor_on_screen();
+    }
+
+    /// Translates a cell on the screen into the document position drawn there
+    fn position_from_screen(&self, column: usize, line: usize) -> Position {
+        let last_line = self.document.len().saturating_sub(1);
+        let width = cmp::max(self.text_width(), 1);
+        let column = column.saturating_sub(self.gutter_width());
+
+        let mut y = self.offset.y;
+        let mut line = line;
+        while y < last_line && line >= self.screen_lines(y) {
+            line -= self.screen_lines(y);
+            y += 1;
+        }
+        let render_x = if self.soft_wrap {
+            line * width + column
         } else {
-            Document::default()
+            self.offset.x.saturating_add(column)
         };
 
-        Self {
-            should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            cursor_position: Position { x: 0, y: 0 },
-            document,
-            offset: Position::default(),
-            ss: ps,
-            status_message: StatusMessage::from(initial_status),
+        Position {
+            x: self.x_from_render_x(y, render_x),
+            y,
         }
     }
-}
 
-fn die(e: std::io::Error) {
-    print!("{}", termion::clear::All);
-    panic!("{}", e);
+    fn on_unmatched_bracket(&self) -> bool {
+        let at = self.cursor_position;
+        let on_bracket = self
+            .document
+            .row(at.y)
+            .and_then(|row| row.grapheme(at.x))
+            .is_some_and(|grapheme| "()[]{}".contains(grapheme));
+        on_bracket && self.document.matching_bracket(&at).is_none()
+    }
+
+    fn keep_cursor_on_screen(&mut self) {
+        let height = self.terminal.size().height as usize;
+        let margin = self.scroll_margin();
+        let Position { x, y } = self.cursor_position;
+        // The margins don't apply once the start or end of the document is in view
+        let top = if self.offset.y == 0 {
+            0
+        } else {
+            self.offset.y.saturating_add(margin)
+        };
+        let bottom = if self.offset.y.saturating_add(height) >= self.document.len() {
+            self.offset.y.saturating_add(height.saturating_sub(1))
+        } else {
+            self.offset
+                .y
+                .saturating_add(height.saturating_sub(1))
+                .saturating_sub(margin)
+        };
+        let y = cmp::min(cmp::max(y, top), bottom);
+        let y = cmp::min(y, self.document.len().saturating_sub(1));
+        let y = self.visible_row(y, SearchDirection::Forward);
+        let width = self.document.row(y).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: cmp::min(x, width),
+            y,
+        };
+    }
+
+    fn keep_cursor_in_document(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let y = cmp::min(y, self.document.len().saturating_sub(1));
+        let width = self.document.row(y).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: cmp::min(x, width),
+            y,
+        };
+        self.scroll();
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.history.record(Edit::Insert {
+            at: self.cursor_position,
+            text: c.to_string(),
+        });
+        self.document.insert(&self.cursor_position, c);
+        self.move_cursor(Key::Right);
+    }
+
+    /// Inserts a typed character, closing brackets and quotes as they are
+    /// opened and typing over the closing ones inserted that way
+    fn type_char(&mut self, h: &HighLightManager, c: char) {
+        if self.overwrite {
+            self.overwrite_char(c);
+            return;
+        }
+        let Position { x, y } = self.cursor_position;
+        let row = self.document.row(y);
+        let blank_row = row.is_none_or(Row::is_blank);
+        let next = row.and_then(|row| row.grapheme(x)).unwrap_or_default();
+        let previous = x
+            .checked_sub(1)
+            .and_then(|x| row?.grapheme(x))
+            .unwrap_or_default();
+
+        let pending = match self.auto_closed {
+            Some((at, count)) if at == self.cursor_position => count,
+            _ => 0,
+        };
+        if pending > 0 && next.starts_with(c) && matches!(c, ')' | ']' | '}' | '"' | '\'') {
+            self.move_cursor(Key::Right);
+            self.auto_closed = Some((self.cursor_position, pending - 1));
+            return;
+        }
+
+        let closing = match c {
+            '(' => Some(')'),
+            '[' => Some(']'),
+            '{' => Some('}'),
+            // Quotes right after a word are more likely apostrophes
+            '"' | '\'' if !previous.chars().any(|p| p.is_alphanumeric() || p == c) => Some(c),
+            _ => None,
+        };
+        // Only close when nothing but whitespace or another closing bracket
+        // follows, so brackets typed in front of existing text stay single
+        let before_text = !next.is_empty()
+            && !next.chars().all(char::is_whitespace)
+            && !matches!(next, ")" | "]" | "}");
+        self.insert_char(c);
+        if let Some(closing) = closing.filter(|_| self.config.auto_close_brackets && !before_text) {
+            self.history.record(Edit::Insert {
+                at: self.cursor_position,
+                text: closing.to_string(),
+            });
+            self.document.insert(&self.cursor_position, closing);
+            self.auto_closed = Some((self.cursor_position, pending + 1));
+        } else if pending > 0 {
+            self.auto_closed = Some((self.cursor_position, pending));
+        }
+        if c == '}' && blank_row && h.indents_braces() {
+            self.align_closing_brace();
+        }
+    }
+
+    /// Moves the `}` just typed on a blank row to the indentation of the row
+    /// opening its block
+    fn align_closing_brace(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let Some(brace_x) = x.checked_sub(1) else {
+            return;
+        };
+        let Some(target) = self
+            .document
+            .matching_bracket(&Position { x: brace_x, y })
+            .filter(|open| open.y < y)
+            .and_then(|open| self.document.row(open.y))
+            .map(|row| row.slice(0, row.first_non_blank()))
+        else {
+            return;
+        };
+        let leading = self
+            .document
+            .row(y)
+            .map(|row| row.slice(0, brace_x))
+            .unwrap_or_default();
+        if leading == target {
+            return;
+        }
+        let start = Position { x: 0, y };
+        let mut edits = Vec::new();
+        if !leading.is_empty() {
+            self.document
+                .delete_range(&start, &Position { x: brace_x, y });
+            edits.push(Edit::Delete {
+                at: start,
+                text: leading,
+            });
+        }
+        if !target.is_empty() {
+            self.document.insert_text(&start, &target);
+            edits.push(Edit::Insert {
+                at: start,
+                text: target.clone(),
+            });
+        }
+        self.history.record(Edit::Group(edits));
+        self.cursor_position.x = target.graphemes(true).count() + 1;
+    }
+
+    /// Types `c` over the character under the cursor, or appends it at the
+    /// end of the row
+    fn overwrite_char(&mut self, c: char) {
+        let at = self.cursor_position;
+        let replacement = c.to_string();
+        match self.document.overwrite(&at, c) {
+            Some(query) => self.history.record(Edit::Replace {
+                at,
+                query,
+                replacement,
+            }),
+            None => self.history.record(Edit::Insert {
+                at,
+                text: replacement,
+            }),
+        }
+        self.move_cursor(Key::Right);
+    }
+
+    /// Deletes the character before the cursor, in overwrite mode as well
+    fn backspace(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        if x > 0 {
+            self.move_cursor(Key::Left);
+            self.delete();
+        } else if y > 0 {
+            let x = self.document.row(y - 1).map_or(0, Row::len);
+            self.cursor_position = Position { x, y: y - 1 };
+            self.delete();
+        }
+    }
+
+    /// Breaks the row at the cursor keeping its indentation, one level
+    /// deeper after a `{` in syntaxes using braces. A `}` right after the
+    /// cursor is then moved down a row to close the block. A comment marker
+    /// or list bullet starting the row is continued on the new one, and
+    /// removed by pressing Enter again before typing anything after it.
+    fn insert_newline(&mut self, h: &HighLightManager) {
+        let at = self.cursor_position;
+        let continued = self.continued.take() == Some((at, self.document.revision()));
+        let mut continuation = self.continuation(h, at);
+        if let Some((start, marker)) = &continuation {
+            if marker.is_empty() && continued {
+                let text = self.document.delete_range(start, &at);
+                self.history.record(Edit::Delete { at: *start, text });
+                self.cursor_position = *start;
+                return;
+            }
+        }
+        // A bare marker typed by hand is left alone
+        continuation = continuation.filter(|(_, marker)| !marker.is_empty());
+        let opens_block = continuation.is_none()
+            && h.indents_braces()
+            && self
+                .document
+                .row(at.y)
+                .is_some_and(|row| row.slice(0, at.x).trim_end().ends_with('{'));
+        let indent = self.document.insert_newline(&at);
+        let mut edits = vec![Edit::Newline { at, indent }];
+        let mut cursor = Position {
+            x: indent,
+            y: at.y.saturating_add(1),
+        };
+        if opens_block {
+            let closes_block = self
+                .document
+                .row(cursor.y)
+                .and_then(|row| row.grapheme(indent))
+                == Some("}");
+            if closes_block {
+                let indent = self.document.insert_newline(&cursor);
+                edits.push(Edit::Newline { at: cursor, indent });
+            }
+            let unit = self.indent_unit();
+            self.document.insert_text(&cursor, &unit);
+            edits.push(Edit::Insert {
+                at: cursor,
+                text: unit.clone(),
+            });
+            cursor.x += unit.len();
+        }
+        if let Some((_, marker)) = continuation {
+            self.document.insert_text(&cursor, &marker);
+            let len = marker.len();
+            edits.push(Edit::Insert {
+                at: cursor,
+                text: marker,
+            });
+            cursor.x += len;
+            self.continued = Some((cursor, self.document.revision()));
+        }
+        let edit = if edits.len() == 1 {
+            edits.remove(0)
+        } else {
+            Edit::Group(edits)
+        };
+        self.history.record(edit);
+        self.cursor_position = cursor;
+    }
+
+    /// Where the comment marker or list bullet starting the row at `at`
+    /// begins, with what continues it on the next row. The continuation is
+    /// empty when the row holds nothing else.
+    fn continuation(&self, h: &HighLightManager, at: Position) -> Option<(Position, String)> {
+        if !self.config.continue_comments {
+            return None;
+        }
+        let row = self.document.row(at.y)?;
+        let indent = row.first_non_blank();
+        let line = row.slice(indent, row.len());
+        let markdown = h.file_type() == "Markdown";
+        let (len, marker) = line_marker(&line, h.comment_token(), markdown)?;
+        if at.x < indent + len {
+            return None;
+        }
+        let start = Position { x: indent, y: at.y };
+        if line[len..].trim().is_empty() && at.x == row.len() {
+            return Some((start, String::new()));
+        }
+        Some((start, marker))
+    }
+
+    /// What one level of indentation is made of
+    fn indent_unit(&self) -> String {
+        if self.insert_spaces {
+            " ".repeat(self.tab_stop)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    fn delete(&mut self) {
+        let at = self.cursor_position;
+        let text = match self.document.row(at.y) {
+            Some(row) if at.x < row.len() => row.grapheme(at.x).map(str::to_string),
+            Some(_) if at.y.saturating_add(1) < self.document.len() => Some("\n".to_string()),
+            _ => None,
+        };
+        if let Some(text) = text {
+            self.history.record(Edit::Delete { at, text });
+            self.document.delete(&at);
+        }
+    }
+
+    /// Adds a level of indentation to the selected rows, or removes one when
+    /// `dedent` is set, without a selection dedenting the cursor's row. The
+    /// selection is kept over the same text.
+    fn shift_lines(&mut self, dedent: bool) {
+        let (first, last) = self.selected_rows();
+        let indent = self.indent_unit();
+
+        let mut edits = Vec::new();
+        let mut shifts = Vec::new();
+        for y in first..=last {
+            let at = Position { x: 0, y };
+            if dedent {
+                if let Some(text) = self.document.dedent_row(y, self.tab_stop) {
+                    shifts.push((y, text.len()));
+                    edits.push(Edit::Delete { at, text });
+                }
+            } else if self.document.indent_row(y, &indent) {
+                shifts.push((y, indent.len()));
+                edits.push(Edit::Insert {
+                    at,
+                    text: indent.clone(),
+                });
+            }
+        }
+        if edits.is_empty() {
+            return;
+        }
+        self.history.record(Edit::Group(edits));
+
+        for position in iter::once(&mut self.cursor_position).chain(&mut self.selection_anchor) {
+            let by = shifts
+                .iter()
+                .find(|(y, _)| *y == position.y)
+                .map_or(0, |(_, by)| *by);
+            position.x = if dedent {
+                position.x.saturating_sub(by)
+            } else {
+                position.x.saturating_add(by)
+            };
+        }
+    }
+
+    /// Comments out the selected rows or the cursor's, or uncomments them
+    /// when they all are already
+    fn toggle_comment(&mut self, h: &HighLightManager) {
+        let Some(token) = h.comment_token() else {
+            self.status_message =
+                StatusMessage::from("ERR: No line comments known for this file type".to_string());
+            return;
+        };
+        let (first, last) = self.selected_rows();
+        self.document.load_rows(last.saturating_add(1));
+        let commented = (first..=last)
+            .filter(|y| !self.document.row(*y).is_none_or(Row::is_blank))
+            .all(|y| self.document.is_commented(y, token));
+
+        let mut edits = Vec::new();
+        let mut shifts = Vec::new();
+        for y in first..=last {
+            if commented {
+                if let Some((at, text)) = self.document.uncomment_row(y, token) {
+                    shifts.push((at, text.len()));
+                    edits.push(Edit::Delete { at, text });
+                }
+            } else if let Some((at, text)) = self.document.comment_row(y, token) {
+                shifts.push((at, text.len()));
+                edits.push(Edit::Insert { at, text });
+            }
+        }
+        if edits.is_empty() {
+            return;
+        }
+        self.history.record(Edit::Group(edits));
+
+        for position in iter::once(&mut self.cursor_position).chain(&mut self.selection_anchor) {
+            let Some((at, by)) = shifts.iter().find(|(at, _)| at.y == position.y) else {
+                continue;
+            };
+            if commented {
+                position.x = cmp::max(position.x.saturating_sub(*by), cmp::min(position.x, at.x));
+            } else if position.x >= at.x {
+                position.x = position.x.saturating_add(*by);
+            }
+        }
+    }
+
+    /// The first and last rows touched by the selection, or the cursor's
+    fn selected_rows(&self) -> (usize, usize) {
+        match self.selection() {
+            // A selection ending at the start of a row doesn't include it
+            Some((start, end)) if end.x == 0 && end.y > start.y => (start.y, end.y - 1),
+            Some((start, end)) => (start.y, end.y),
+            None => (self.cursor_position.y, self.cursor_position.y),
+        }
+    }
+
+    /// The selected part of the document as ordered `(start, end)` positions,
+    /// if anything is selected
+    fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.cursor_position;
+        match (anchor.y, anchor.x).cmp(&(cursor.y, cursor.x)) {
+            cmp::Ordering::Less => Some((anchor, cursor)),
+            cmp::Ordering::Greater => Some((cursor, anchor)),
+            cmp::Ordering::Equal => None,
+        }
+    }
+
+    fn extend_selection(&mut self, key: Key) {
+        if self.selection_anchor.is_none() {
+            self.selection_anchor = Some(self.cursor_position);
+        }
+        let key = match key {
+            Key::ShiftLeft => Key::Left,
+            Key::ShiftRight => Key::Right,
+            Key::ShiftUp => Key::Up,
+            _ => Key::Down,
+        };
+        self.move_cursor(key);
+    }
+
+    /// Deletes the selected text, returning it
+    fn delete_selection(&mut self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        let text = self.document.delete_range(&start, &end);
+        self.history.record(Edit::Delete {
+            at: start,
+            text: text.clone(),
+        });
+        self.cursor_position = start;
+        self.selection_anchor = None;
+        Some(text)
+    }
+
+    fn cut(&mut self) {
+        if let Some(text) = self.delete_selection() {
+            self.set_clipboard(Clipboard::Text(text));
+            return;
+        }
+        let y = self.cursor_position.y;
+        let placeholder = self.document.len() == 1;
+        let Some(text) = self.document.remove_row(y) else {
+            return;
+        };
+        self.history.record(Edit::RemoveRow {
+            y,
+            text: text.clone(),
+            placeholder,
+        });
+        self.set_clipboard(Clipboard::Line(text));
+        self.cursor_position.y = cmp::min(y, self.document.len().saturating_sub(1));
+        self.keep_cursor_on_screen();
+    }
+
+    fn copy(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            let text = self.document.text_between(&start, &end);
+            self.status_message = StatusMessage::from("Selection copied".to_string());
+            self.set_clipboard(Clipboard::Text(text));
+        } else if let Some(row) = self.document.row(self.cursor_position.y) {
+            let text = row.as_str().to_string();
+            self.status_message = StatusMessage::from("Line copied".to_string());
+            self.set_clipboard(Clipboard::Line(text));
+        }
+    }
+
+    /// Inserts a copy of the cursor's line below it, moving the cursor onto
+    /// the copy
+    fn duplicate_line(&mut self) {
+        let y = self.cursor_position.y;
+        let Some(text) = self.document.duplicate_row(y) else {
+            return;
+        };
+        self.history.record(Edit::InsertRow {
+            y: y.saturating_add(1),
+            text,
+        });
+        self.cursor_position.y = y.saturating_add(1);
+    }
+
+    /// Swaps the cursor's line with the one above or below, the cursor
+    /// moving along with it
+    fn move_line(&mut self, direction: SearchDirection) {
+        let y = self.cursor_position.y;
+        let Some((target, text)) = self.document.move_row(y, direction) else {
+            return;
+        };
+        self.history.record(Edit::Group(vec![
+            Edit::RemoveRow {
+                y,
+                text: text.clone(),
+                placeholder: false,
+            },
+            Edit::InsertRow { y: target, text },
+        ]));
+        self.cursor_position.y = target;
+    }
+
+    /// Keeps `clipboard` for pasting, and hands it to the terminal too when
+    /// `osc52_clipboard` is on
+    fn set_clipboard(&mut self, clipboard: Clipboard) {
+        if self.config.osc52_clipboard {
+            let text = match &clipboard {
+                Clipboard::Line(text) => format!("{text}\n"),
+                Clipboard::Text(text) => text.clone(),
+            };
+            match Terminal::set_clipboard(&mut self.terminal.output(), &text) {
+                Ok(true) => (),
+                Ok(false) => {
+                    self.status_message = StatusMessage::from(
+                        "WARNING: Too long for the system clipboard".to_string(),
+                    );
+                }
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Could not set the clipboard: {error}"));
+                }
+            }
+        }
+        self.clipboard = Some(clipboard);
+    }
+
+    /// Pastes a copied line above the cursor, keeping the cursor on it, or
+    /// copied text at the cursor, moving the cursor past it
+    fn paste(&mut self) {
+        match self.clipboard.clone() {
+            Some(Clipboard::Line(text)) => {
+                let y = self.cursor_position.y;
+                self.document.insert_row(y, &text);
+                self.history.record(Edit::InsertRow { y, text });
+                let width = self.document.row(y).map_or(0, Row::len);
+                self.cursor_position.x = cmp::min(self.cursor_position.x, width);
+            }
+            Some(Clipboard::Text(text)) => {
+                let at = self.cursor_position;
+                self.cursor_position = self.document.insert_text(&at, &text);
+                self.history.record(Edit::Insert { at, text });
+            }
+            None => (),
+        }
+    }
+
+    /// Inserts text pasted into the terminal as it is, replacing the
+    /// selection, without the auto-indent and auto-close typing would do
+    fn paste_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.read_only || self.document.is_directory() {
+            self.status_message =
+                StatusMessage::from("ERR: Can't paste into a read-only document".to_string());
+            return;
+        }
+        if self.selection().is_some() {
+            self.delete_selection();
+        }
+        let at = self.cursor_position;
+        self.cursor_position = self.document.insert_text(&at, text);
+        self.history.record(Edit::Insert {
+            at,
+            text: text.to_string(),
+        });
+        self.selection_anchor = None;
+        self.auto_closed = None;
+        self.scroll();
+    }
+
+    fn open(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let file_name = self.prompt("Open: ", h, |_, _, _, _| {})?;
+        if let Some(file_name) = file_name.filter(|file_name| !file_name.is_empty()) {
+            self.open_file(h, &file_name)?;
+        }
+        Ok(())
+    }
+
+    /// Switches to the buffer editing `file_name`, opening it if needed,
+    /// and returns whether it could
+    fn open_file(
+        &mut self,
+        h: &mut HighLightManager,
+        file_name: &str,
+    ) -> Result<bool, std::io::Error> {
+        if let Some(index) = self.buffer_index(file_name) {
+            self.switch_buffer(h, index);
+            return Ok(true);
+        }
+        match Document::open(file_name) {
+            Ok(document) => {
+                if let Some(warning) = open_warning(&document) {
+                    self.status_message = StatusMessage::from(warning);
+                }
+                let mut document = document;
+                let cursor_position = remembered_cursor(&mut document);
+                recent_files::add(&[file_name]).ok();
+                self.buffers.push(Buffer {
+                    document,
+                    cursor_position,
+                    ..Buffer::default()
+                });
+                self.switch_buffer(h, self.buffers.len() - 1);
+                self.offer_read_only(h)?;
+                self.offer_recovery(h)?;
+                Ok(true)
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not open file: {file_name}: {error}"));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Reads the current file again, asking first when that would drop
+    /// unsaved changes
+    fn reload(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let Some(file_name) = self.file_name().map(str::to_string) else {
+            self.status_message = StatusMessage::from("ERR: No file name to reload".to_string());
+            return Ok(());
+        };
+        if self.document.is_dirty()
+            && !self.confirm(
+                &format!("{file_name} has unsaved changes, discard them and reload? (y/n): "),
+                h,
+            )?
+        {
+            self.status_message = StatusMessage::from("Reload aborted.".to_string());
+            return Ok(());
+        }
+        match Document::open(&file_name) {
+            Ok(document) => {
+                self.document.remove_swap();
+                self.document = document;
+                self.history = History::default();
+                self.folds = Folds::default();
+                self.selection_anchor = None;
+                self.keep_cursor_in_document();
+                self.document.reset_highlighting();
+                self.apply_indent();
+                self.status_message = StatusMessage::from(
+                    open_warning(&self.document).unwrap_or(format!("Reloaded {file_name}")),
+                );
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not reload {file_name}: {error}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the entry under the cursor in a directory listing, listing a
+    /// directory in place of the current one and a file in its own buffer
+    fn open_entry(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let Some(path) = self.document.entry_path(self.cursor_position.y) else {
+            return Ok(());
+        };
+        let Some(file_name) = path.to_str().map(str::to_string) else {
+            self.status_message =
+                StatusMessage::from(format!("ERR: Can't open {}", path.display()));
+            return Ok(());
+        };
+        if !path.is_dir() {
+            return self.open_file(h, &file_name).map(|_| ());
+        }
+        match Document::open(&file_name) {
+            Ok(document) => {
+                self.document = document;
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                self.scroll();
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not list {file_name}: {error}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches to a new buffer holding an empty unnamed document, which
+    /// asks for a name when saved
+    fn new_buffer(&mut self, h: &mut HighLightManager) {
+        self.buffers.push(Buffer::default());
+        self.switch_buffer(h, self.buffers.len() - 1);
+        self.status_message = StatusMessage::from(format!(
+            "New buffer {} of {}",
+            self.current_buffer + 1,
+            self.buffers.len()
+        ));
+    }
+
+    /// Lets the user pick a file under the current directory by fuzzy
+    /// matching its path
+    fn find_file(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        self.pick_file(
+            h,
+            "Find file (ESC to cancel, Up/Down to select): ",
+            Finder::new(Path::new(".")),
+        )
+    }
+
+    /// Lets the user pick one of the files opened lately, the latest first
+    fn open_recent(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let files = recent_files::load();
+        if files.is_empty() {
+            self.status_message = StatusMessage::from("No recent files".to_string());
+            return Ok(());
+        }
+        self.pick_file(
+            h,
+            "Recent file (ESC to cancel, Up/Down to select): ",
+            Finder::from_files(files),
+        )
+    }
+
+    /// Opens the file selected in `finder`, narrowing it down with what is
+    /// typed at the prompt. Enter with nothing typed opens the first one.
+    fn pick_file(
+        &mut self,
+        h: &mut HighLightManager,
+        prompt: &str,
+        finder: Finder,
+    ) -> Result<(), std::io::Error> {
+        self.finder = Some(finder);
+        let query = self.prompt(prompt, h, |editor, _, key, query| {
+            let Some(finder) = editor.finder.as_mut() else {
+                return;
+            };
+            match key {
+                Key::Up => finder.select_previous(),
+                Key::Down => finder.select_next(),
+                _ => finder.filter(query),
+            }
+        });
+        let finder = self.finder.take();
+        if query?.is_some() {
+            match finder.as_ref().and_then(Finder::selected) {
+                Some(file_name) => {
+                    self.open_file(h, file_name)?;
+                }
+                None => {
+                    self.status_message = StatusMessage::from("ERR: No matching file".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let input = self.prompt(":", h, |_, _, _, _| {})?;
+        let Some(input) = input.filter(|input| !input.trim().is_empty()) else {
+            return Ok(());
+        };
+        match Command::parse(&input) {
+            Ok(command) => self.run_command(h, command)?,
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
+        }
+        Ok(())
+    }
+
+    fn run_command(
+        &mut self,
+        h: &mut HighLightManager,
+        command: Command,
+    ) -> Result<(), std::io::Error> {
+        match command {
+            Command::Write(file_name) => {
+                if let Some(file_name) = file_name {
+                    self.set_file_name(h, file_name);
+                }
+                self.save(h)?;
+            }
+            Command::Quit { force: false } if self.dirty_buffers() > 0 => {
+                self.status_message = StatusMessage::from(
+                    "ERR: There are unsaved changes, use :q! to quit anyway".to_string(),
+                );
+            }
+            Command::Quit { .. } => {
+                self.remember_files();
+                self.remove_swaps();
+                self.should_quit = true;
+            }
+            Command::WriteQuit => {
+                self.save(h)?;
+                if !self.read_only && !self.document.is_dirty() {
+                    self.run_command(h, Command::Quit { force: false })?;
+                }
+            }
+            Command::Edit(file_name) => {
+                self.open_file(h, &file_name)?;
+            }
+            Command::Reload => self.reload(h)?,
+            Command::View(file_name) => {
+                let opened = match file_name {
+                    Some(file_name) => self.open_file(h, &file_name)?,
+                    None => true,
+                };
+                if opened {
+                    self.read_only = true;
+                }
+            }
+            Command::Set { option, enabled } => match option.as_str() {
+                "number" | "nu" => self.show_line_numbers = enabled,
+                "readonly" | "ro" => self.read_only = enabled,
+                "autoclose" => self.config.auto_close_brackets = enabled,
+                "minimap" => self.config.show_minimap = enabled,
+                "continue" => self.config.continue_comments = enabled,
+                "osc52" => self.config.osc52_clipboard = enabled,
+                "wrapcursor" => self.config.wrap_cursor = enabled,
+                "whitespace" => self.config.show_whitespace_errors = enabled,
+                "highlight" | "hl" => self.document.set_plain(!enabled),
+                "wrap" => {
+                    self.soft_wrap = enabled;
+                    self.offset.x = 0;
+                }
+                _ => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Unknown option: {option}"));
+                }
+            },
+            Command::Theme(theme) => self.change_theme(h, &theme),
+            Command::Count => self.count(),
+            Command::Recent => self.open_recent(h)?,
+            Command::New => self.new_buffer(h),
+            Command::Conflict(ConflictAction::Next) => {
+                self.jump_to_conflict(SearchDirection::Forward);
+            }
+            Command::Conflict(ConflictAction::Previous) => {
+                self.jump_to_conflict(SearchDirection::Backward);
+            }
+            Command::Conflict(ConflictAction::Ours) => self.resolve_conflict(true),
+            Command::Conflict(ConflictAction::Theirs) => self.resolve_conflict(false),
+        }
+        Ok(())
+    }
+
+    /// Reports the lines, words and characters of the selection, or of the
+    /// whole document when nothing is selected. Line breaks aren't counted
+    /// as characters.
+    fn count(&mut self) {
+        let (start, end, what) = match self.selection() {
+            Some((start, end)) => (start, end, "Selection"),
+            None => (
+                Position::default(),
+                Position {
+                    x: usize::MAX,
+                    y: usize::MAX,
+                },
+                "Document",
+            ),
+        };
+        let text = self.document.text_between(&start, &end);
+        let (mut lines, mut words, mut characters) = (0, 0, 0);
+        for line in text.split('\n') {
+            let row = Row::from(line);
+            lines += 1;
+            words += row.word_count();
+            characters += row.len();
+        }
+        let plural = |count: usize, noun: &str| {
+            let s = if count == 1 { "" } else { "s" };
+            format!("{count} {noun}{s}")
+        };
+        self.status_message = StatusMessage::from(format!(
+            "{what}: {}, {}, {}",
+            plural(lines, "line"),
+            plural(words, "word"),
+            plural(characters, "character")
+        ));
+    }
+
+    /// Index of the buffer already editing `file_name`
+    fn buffer_index(&self, file_name: &str) -> Option<usize> {
+        if self.document.file_name.as_deref() == Some(file_name) {
+            return Some(self.current_buffer);
+        }
+        self.buffers
+            .iter()
+            .position(|buffer| buffer.document.file_name.as_deref() == Some(file_name))
+    }
+
+    fn switch_buffer(&mut self, h: &mut HighLightManager, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        self.swap_buffer(self.current_buffer);
+        self.current_buffer = index;
+        self.swap_buffer(index);
+        h.detect_syntax(self.document.file_name.as_deref());
+        self.document.reset_highlighting();
+        self.apply_indent();
+    }
+
+    /// Follows the indentation style of the current document, or the
+    /// configured one when it can't be told
+    fn apply_indent(&mut self) {
+        match self.document.indent() {
+            Some(Indent::Tabs) => {
+                self.tab_stop = cmp::max(self.config.tab_stop, 1);
+                self.insert_spaces = false;
+            }
+            Some(Indent::Spaces(size)) => {
+                self.tab_stop = size;
+                self.insert_spaces = true;
+            }
+            None => {
+                self.tab_stop = cmp::max(self.config.tab_stop, 1);
+                self.insert_spaces = self.config.insert_spaces;
+            }
+        }
+    }
+
+    /// Exchanges the state of the buffer being edited with the one stored at `index`
+    fn swap_buffer(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        mem::swap(&mut self.document, &mut buffer.document);
+        mem::swap(&mut self.cursor_position, &mut buffer.cursor_position);
+        mem::swap(&mut self.offset, &mut buffer.offset);
+        mem::swap(&mut self.history, &mut buffer.history);
+        mem::swap(&mut self.folds, &mut buffer.folds);
+        mem::swap(&mut self.read_only, &mut buffer.read_only);
+    }
+
+    fn dirty_buffers(&self) -> usize {
+        let others = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.document.is_dirty())
+            .count();
+        others + usize::from(self.document.is_dirty())
+    }
+
+    fn set_file_name(&mut self, h: &mut HighLightManager, file_name: String) {
+        self.document.file_name = Some(file_name);
+        h.detect_syntax(self.document.file_name.as_deref());
+        self.document.reset_highlighting();
+    }
+
+    fn save(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        if self.read_only {
+            self.status_message = StatusMessage::from(
+                "ERR: Not saving in read-only mode, use :set noreadonly first".to_string(),
+            );
+            return Ok(());
+        }
+        if self.document.is_directory() {
+            self.status_message =
+                StatusMessage::from("ERR: A directory listing can't be saved".to_string());
+            return Ok(());
+        }
+        if self.document.file_name.is_none() {
+            let new_name = self.prompt("Save as: ", h, |_, _, _, _| {})?;
+            let Some(new_name) = new_name.filter(|new_name| !new_name.is_empty()) else {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            };
+            self.set_file_name(h, new_name);
+        }
+        // Big files are only checked for invalid UTF-8 as their rows are read
+        self.document.load_rows(self.document.len());
+        if self.document.is_lossy() {
+            let file_name = self.file_name().unwrap_or_default().to_string();
+            if !self.confirm(&format!("{file_name} wasn't valid UTF-8, saving loses the invalid bytes. Save anyway? (y/n): "), h)? {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            }
+        }
+
+        // Trimming goes into the history as a single step so that undo keeps
+        // working across saves
+        let mut trimmed = Vec::new();
+        if self.config.trim_trailing_whitespace {
+            trimmed.extend(self.document.trim_trailing_whitespace());
+        }
+        if self.config.final_newline {
+            trimmed.extend(self.document.trim_trailing_blank_rows());
+            // An empty document stays an empty file
+            if !self.document.is_empty() {
+                self.document.add_final_line_ending();
+            }
+        }
+        if !trimmed.is_empty() {
+            let edits = trimmed
+                .into_iter()
+                .map(|(at, text)| Edit::Delete { at, text })
+                .collect();
+            self.history.record(Edit::Group(edits));
+        }
+        // The cursor may have been sitting on whitespace that is now gone
+        self.keep_cursor_in_document();
+
+        self.status_message = match self.document.save() {
+            Ok(bytes) => {
+                let file_name = self.file_name().unwrap_or_default();
+                cursor_history::store(&[(file_name, self.cursor_position)]).ok();
+                StatusMessage::from(format!(
+                    "{} lines, {bytes} bytes written to {file_name}",
+                    self.document.len(),
+                ))
+            }
+            Err(error) => StatusMessage::from(format!("ERR: Could not write file: {error}")),
+        };
+        Ok(())
+    }
+
+    fn search(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let old_position = self.cursor_position;
+        let mut direction = SearchDirection::Forward;
+        let mut use_regex = false;
+        let query = self.prompt(
+            "Search (ESC to cancel, Arrows to navigate, Alt-r for regex): ",
+            h,
+            |editor, _, key, query| {
+                let mut moved = false;
+                match key {
+                    Key::Right | Key::Down => {
+                        direction = SearchDirection::Forward;
+                        editor.move_cursor(Key::Right);
+                        moved = true;
+                    }
+                    Key::Left | Key::Up => direction = SearchDirection::Backward,
+                    Key::Alt('r') => use_regex = !use_regex,
+                    _ => direction = SearchDirection::Forward,
+                }
+                let at = editor.cursor_position;
+                let found = if use_regex {
+                    if let Ok(re) = Regex::new(query) {
+                        editor.status_message = StatusMessage::from(format!(
+                            "Regex search (ESC to cancel, Arrows to navigate, Alt-r for plain): {query}"
+                        ));
+                        let found = editor.document.find_regex(&re, &at, direction);
+                        editor.search_pattern = Some(re);
+                        found
+                    } else {
+                        editor.status_message = StatusMessage::from(format!(
+                            "Regex search: {query} ERR: Invalid regex"
+                        ));
+                        editor.search_pattern = None;
+                        None
+                    }
+                } else {
+                    editor.search_pattern = Regex::new(&regex::escape(query)).ok();
+                    editor.document.find(query, &at, direction)
+                };
+                if let Some(position) = found {
+                    editor.cursor_position = position;
+                    editor.scroll();
+                } else if moved {
+                    editor.move_cursor(Key::Left);
+                }
+                editor.show_match_count();
+            },
+        )?;
+        let pattern = self.search_pattern.take();
+        match query {
+            Some(query) if !query.is_empty() => self.last_search = pattern,
+            // Enter with nothing typed is as good as Esc
+            Some(_) => {
+                self.cursor_position = old_position;
+                self.scroll();
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Appends which of the matches of the search being typed the cursor is
+    /// on to the message bar
+    fn show_match_count(&mut self) {
+        let Some(re) = self.search_pattern.clone() else {
+            return;
+        };
+        if let Some(count) = self.match_count(&re) {
+            self.status_message.text = format!("{} ({count})", self.status_message.text);
+        }
+    }
+
+    /// Which match of `re` the cursor is on out of how many, like `match 2
+    /// of 5`. Only the rows read so far of a big file are counted, the count
+    /// then ending with `+`.
+    fn match_count(&mut self, re: &Regex) -> Option<String> {
+        let Position { x, y } = self.cursor_position;
+        let (matches, complete) = self.document.find_all(re);
+        if matches.is_empty() {
+            return None;
+        }
+        let current = matches.partition_point(|position| (position.y, position.x) <= (y, x));
+        let more = if complete { "" } else { "+" };
+        Some(format!(
+            "match {} of {}{more}",
+            cmp::max(current, 1),
+            matches.len()
+        ))
+    }
+
+    /// Jumps to the next or previous match of the last search, wrapping
+    /// around the document
+    fn repeat_search(&mut self, direction: SearchDirection) {
+        let Some(re) = self.last_search.clone() else {
+            self.status_message = StatusMessage::from("ERR: No previous search".to_string());
+            return;
+        };
+        let Position { x, y } = self.cursor_position;
+        let (at, wrap_at, wrap_message) = if direction == SearchDirection::Forward {
+            (
+                Position { x: x + 1, y },
+                Position::default(),
+                "search hit BOTTOM, continuing at TOP",
+            )
+        } else {
+            self.document.load_rows(self.document.len());
+            let last = self.document.len().saturating_sub(1);
+            let end = Position {
+                x: self.document.row(last).map_or(0, Row::len),
+                y: last,
+            };
+            (
+                self.cursor_position,
+                end,
+                "search hit TOP, continuing at BOTTOM",
+            )
+        };
+
+        let mut message = None;
+        let mut found = self.document.find_regex(&re, &at, direction);
+        if found.is_none() {
+            found = self.document.find_regex(&re, &wrap_at, direction);
+            message = Some(wrap_message.to_string());
+        }
+        let Some(position) = found else {
+            self.status_message = StatusMessage::from("ERR: Pattern not found".to_string());
+            return;
+        };
+        self.cursor_position = position;
+        let message = message.unwrap_or_else(|| self.match_count(&re).unwrap_or_default());
+        self.status_message = StatusMessage::from(message);
+    }
+
+    fn replace(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let query = self.prompt("Replace: ", h, |_, _, _, _| {})?;
+        let Some(query) = query.filter(|query| !query.is_empty()) else {
+            return Ok(());
+        };
+        let Some(replacement) =
+            self.prompt(&format!("Replace {query} with: "), h, |_, _, _, _| {})?
+        else {
+            return Ok(());
+        };
+
+        let replacement_len = replacement.graphemes(true).count();
+        let mut replaced = 0;
+        let mut replace_all = false;
+        let mut at = self.cursor_position;
+        let old_position = self.cursor_position;
+        let old_offset = self.offset;
+        while let Some(position) = self.document.find(&query, &at, SearchDirection::Forward) {
+            self.cursor_position = position;
+            self.scroll();
+            let mut confirmed = replace_all;
+            if !replace_all {
+                let answer = self.choose(
+                    "Replace? (y = yes, n = no, a = all, ESC = stop)",
+                    &['y', 'n', 'a'],
+                    h,
+                )?;
+                match answer {
+                    Some('y') => confirmed = true,
+                    Some('a') => {
+                        confirmed = true;
+                        replace_all = true;
+                    }
+                    Some(_) => (),
+                    None => {
+                        self.cursor_position = old_position;
+                        self.offset = old_offset;
+                        break;
+                    }
+                }
+            }
+            if confirmed {
+                self.history.record(Edit::Replace {
+                    at: position,
+                    query: query.clone(),
+                    replacement: replacement.clone(),
+                });
+                self.document.replace(&position, &query, &replacement);
+                replaced += 1;
+                at = Position {
+                    x: position.x + replacement_len,
+                    y: position.y,
+                };
+            } else {
+                at = Position {
+                    x: position.x + 1,
+                    y: position.y,
+                };
+            }
+        }
+
+        self.status_message = StatusMessage::from(format!("{replaced} occurrences replaced"));
+        Ok(())
+    }
+
+    fn go_to_line(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let input = self.prompt("Go to line: ", h, |_, _, _, _| {})?;
+        if let Some(input) = input.filter(|input| !input.trim().is_empty()) {
+            match input.trim().parse::<usize>() {
+                Ok(line) if line > 0 => {
+                    let y = cmp::min(line, cmp::max(self.document.len(), 1)) - 1;
+                    self.document.load_rows(line);
+                    let width = self.document.row(y).map_or(0, Row::len);
+                    self.cursor_position = Position {
+                        x: cmp::min(self.cursor_position.x, width),
+                        y,
+                    };
+                    self.scroll();
+                }
+                _ => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Invalid line number: {input}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn change_theme(&mut self, h: &mut HighLightManager, theme: &str) {
+        self.status_message = match h.change_theme(theme) {
+            Ok(()) => {
+                self.document.reset_highlighting();
+                StatusMessage::from(format!("Theme: {theme}"))
+            }
+            Err(error) => StatusMessage::from(format!("ERR: {error}")),
+        };
+    }
+
+    fn pick_theme(&mut self, h: &mut HighLightManager) -> Result<(), std::io::Error> {
+        let query = self.prompt("Theme: ", h, |editor, h, _, query| {
+            let matches = matching_themes(h, query);
+            editor.status_message.text =
+                format!("{} [{}]", editor.status_message.text, matches.join(" | "));
+        })?;
+
+        if let Some(query) = query.filter(|query| !query.is_empty()) {
+            let matches = matching_themes(h, &query);
+            let theme = matches
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(&query))
+                .or_else(|| matches.first())
+                .map(|name| (*name).to_string());
+            match theme {
+                Some(theme) => self.change_theme(h, &theme),
+                None => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Unknown theme: {query}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks a yes or no `question`, anything but `y` being no
+    fn confirm(
+        &mut self,
+        question: &str,
+        h: &mut HighLightManager,
+    ) -> Result<bool, std::io::Error> {
+        let answer = self.prompt(question, h, |_, _, _, _| {})?;
+        Ok(answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y")))
+    }
+
+    /// Asks `question` in the message bar and waits for one of `answers` to
+    /// be typed, any other key answering `None` like Esc does
+    fn choose(
+        &mut self,
+        question: &str,
+        answers: &[char],
+        h: &mut HighLightManager,
+    ) -> Result<Option<char>, std::io::Error> {
+        self.prompting = true;
+        self.status_message = StatusMessage::from(question.to_string());
+        self.render_frame(&mut self.terminal.output(), h)?;
+        let key = self.terminal.read_key()?;
+        self.prompting = false;
+        self.status_message = StatusMessage::from(String::new());
+        Ok(match key {
+            Key::Char(c) if answers.contains(&c) => Some(c),
+            _ => None,
+        })
+    }
+
+    /// Reads a line typed in the message bar after `prompt`, calling
+    /// `callback` after every key. Enter returns what was typed, even if
+    /// nothing was, while Esc returns `None` and puts the cursor and the
+    /// view back where they were.
+    fn prompt<C>(
+        &mut self,
+        prompt: &str,
+        h: &mut HighLightManager,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, &mut HighLightManager, Key, &String),
+    {
+        let mut result = String::new();
+        let old_position = self.cursor_position;
+        let old_offset = self.offset;
+        let mut accepted = false;
+        self.prompting = true;
+        self.status_message = StatusMessage::from(prompt.to_string());
+        callback(self, h, Key::Null, &result);
+        loop {
+            self.render_frame(&mut self.terminal.output(), h)?;
+            let key = self.terminal.read_key()?;
+            match key {
+                Key::Backspace => {
+                    result.pop();
+                }
+                Key::Char('\n') => {
+                    accepted = true;
+                    break;
+                }
+                Key::Char(c) if !c.is_control() => result.push(c),
+                Key::Esc => break,
+                _ => (),
+            }
+            self.status_message = StatusMessage::from(format!("{prompt}{result}"));
+            callback(self, h, key, &result);
+        }
+        self.prompting = false;
+        self.status_message = StatusMessage::from(String::new());
+        if !accepted {
+            self.cursor_position = old_position;
+            self.offset = old_offset;
+            return Ok(None);
+        }
+        Ok(Some(result))
+    }
+
+    fn gutter_width(&self) -> usize {
+        if self.show_line_numbers {
+            self.document.len().max(1).to_string().len() + 1
+        } else {
+            0
+        }
+    }
+
+    fn text_width(&self) -> usize {
+        (self.terminal.size().width as usize)
+            .saturating_sub(self.gutter_width())
+            .saturating_sub(self.minimap_width())
+            .saturating_sub(SCROLLBAR_WIDTH)
+    }
+
+    fn minimap_width(&self) -> usize {
+        if self.config.show_minimap {
+            MINIMAP_WIDTH
+        } else {
+            0
+        }
+    }
+
+    /// How many document rows each line of a minimap `rows` lines high
+    /// stands for, so that the whole document fits in it
+    fn minimap_scale(&self, rows: usize) -> usize {
+        cmp::max(self.document.len().div_ceil(cmp::max(rows, 1)), 1)
+    }
+
+    /// The document rows from `offset.y` on that fill the first `rows`
+    /// terminal rows, counting folded and wrapped rows
+    fn rows_on_screen(&self, rows: usize) -> Range<usize> {
+        let start = self.offset.y;
+        let mut end = start;
+        let mut lines = 0;
+        while lines < rows && end < self.document.len() {
+            lines += self.screen_lines(end);
+            end += 1;
+        }
+        start..end
+    }
+
+    /// How many lines row `y` takes on screen: none when it is folded
+    /// away, more than one when it is soft wrapped
+    fn screen_lines(&self, y: usize) -> usize {
+        if self.folds.hiding(y, self.document.len()).is_some() {
+            return 0;
+        }
+        if !self.soft_wrap {
+            return 1;
+        }
+        let width = cmp::max(self.text_width(), 1);
+        self.document
+            .row(y)
+            .map_or(1, |row| row.render_len(self.tab_stop) / width + 1)
+    }
+
+    /// Row `y`, or when it is folded away the closest row shown in
+    /// `direction`, falling back to the row the fold is shown as
+    fn visible_row(&self, y: usize, direction: SearchDirection) -> usize {
+        let len = self.document.len();
+        match self.folds.hiding(y, len) {
+            Some(hidden) if direction == SearchDirection::Forward && hidden.end < len => hidden.end,
+            Some(hidden) => hidden.start.saturating_sub(1),
+            None => y,
+        }
+    }
+
+    fn cursor_render_x(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        self.document
+            .row(y)
+            .map_or(0, |row| row.render_x(x, self.tab_stop))
+    }
+
+    fn cursor_screen_position(&self) -> Position {
+        let x = self.cursor_render_x();
+        let y = self.cursor_position.y;
+        let lines_above: usize = (self.offset.y..y).map(|y| self.screen_lines(y)).sum();
+        if !self.soft_wrap {
+            return Position {
+                x: x.saturating_sub(self.offset.x),
+                y: lines_above,
+            };
+        }
+        let width = cmp::max(self.text_width(), 1);
+        Position {
+            x: x % width,
+            y: lines_above + x / width,
+        }
+    }
+
+    /// How many lines to keep visible around the cursor, at most half the screen
+    fn scroll_margin(&self) -> usize {
+        let height = self.terminal.size().height as usize;
+        cmp::min(self.config.scrolloff, height.saturating_sub(1) / 2)
+    }
+
+    fn scroll(&mut self) {
+        let x = self.cursor_render_x();
+        let y = self.cursor_position.y;
+        let width = self.text_width();
+        let height = self.terminal.size().height as usize;
+        let margin = self.scroll_margin();
+        let len = self.document.len();
+        // Whatever moved the cursor into a fold wants it seen
+        self.folds.reveal(y);
+
+        if y < self.offset.y.saturating_add(margin) {
+            self.offset.y = y.saturating_sub(margin);
+        }
+
+        // Counted in screen lines so that folds and wrapped rows are
+        // accounted for
+        let below: usize = (y.saturating_add(1)..cmp::min(y.saturating_add(1 + margin), len))
+            .map(|y| self.screen_lines(y))
+            .sum();
+        let mut screen_y = self.cursor_screen_position().y;
+        while screen_y + below >= height && self.offset.y < y {
+            screen_y -= self.screen_lines(self.offset.y);
+            self.offset.y += 1;
+        }
+        if self.soft_wrap {
+            self.offset.x = 0;
+            return;
+        }
+
+        // `width` leaves out the gutter, so the margin is counted from where
+        // the text starts
+        let side_margin = cmp::min(self.config.sidescrolloff, width.saturating_sub(1) / 2);
+        let row = self.document.row(y);
+        let row_width = row.map_or(0, |row| row.render_len(self.tab_stop));
+        if x < self.offset.x.saturating_add(side_margin) {
+            self.offset.x = x.saturating_sub(side_margin);
+        }
+        // A tab or wide character under the cursor is brought fully into
+        // view, otherwise the cursor would sit on the padding drawn for it
+        let last = row.map_or(x, |row| {
+            let cursor = row.render_range(self.cursor_position.x, self.tab_stop);
+            cursor.end.saturating_sub(1)
+        });
+        let right = cmp::max(last, cmp::min(last.saturating_add(side_margin), row_width));
+        if right >= self.offset.x.saturating_add(width) {
+            self.offset.x = right.saturating_sub(width).saturating_add(1);
+        }
+    }
+
+    fn move_cursor(&mut self, key: Key) {
+        let Position { mut y, mut x } = self.cursor_position;
+
+        let mut width = self.document.row(y).map_or(0, Row::len);
+        let height = self.document.len();
+        let terminal_height = self.terminal.size().height as usize;
+
+        let desired_column = match self.desired_column {
+            Some((column, at)) if at == self.cursor_position => column,
+            _ => self.cursor_render_x(),
+        };
+        let vertical =
+            !self.soft_wrap && matches!(key, Key::Up | Key::Down | Key::PageUp | Key::PageDown);
+
+        match key {
+            Key::Up | Key::Down if self.soft_wrap => {
+                if let Some(position) = self.wrapped_line_position(key) {
+                    Position { x, y } = position;
+                }
+            }
+            Key::Up => y = y.saturating_sub(1),
+            Key::Down if y.saturating_add(1) < height => y = y.saturating_add(1),
+            Key::CtrlLeft if x == 0 && y > 0 => {
+                y -= 1;
+                x = self.document.row(y).map_or(0, Row::len);
+            }
+            Key::CtrlLeft => {
+                x = self
+                    .document
+                    .row(y)
+                    .map_or(0, |row| row.previous_word_start(x));
+            }
+            Key::CtrlRight if x >= width && y.saturating_add(1) < height => {
+                y = y.saturating_add(1);
+                x = 0;
+            }
+            Key::CtrlRight => {
+                x = self.document.row(y).map_or(0, |row| row.next_word_start(x));
+            }
+            Key::Left if x == 0 && y > 0 && self.config.wrap_cursor => {
+                // Clamped to the length of the row once it is known, which
+                // may be a fold shown in place of the row above
+                y -= 1;
+                x = usize::MAX;
+            }
+            Key::Left => x = x.saturating_sub(1),
+            Key::Right if x < width => x = x.saturating_add(1),
+            Key::Right if y.saturating_add(1) < height && self.config.wrap_cursor => {
+                y = y.saturating_add(1);
+                x = 0;
+            }
+            Key::PageUp => {
+                y = y.saturating_sub(terminal_height);
+                self.offset.y = self.offset.y.saturating_sub(terminal_height);
+            }
+            Key::PageDown => {
+                y = cmp::min(y.saturating_add(terminal_height), height.saturating_sub(1));
+                self.offset.y = cmp::min(
+                    self.offset.y.saturating_add(terminal_height),
+                    height.saturating_sub(terminal_height.saturating_sub(1)),
+                );
+            }
+            Key::Home => x = self.home_x(y, x),
+            Key::End => x = width,
+            Key::CtrlHome => {
+                y = 0;
+                x = 0;
+            }
+            Key::CtrlEnd => {
+                // The last row may not be loaded yet, so x is clamped to
+                // its length below
+                y = height.saturating_sub(1);
+                x = usize::MAX;
+            }
+            _ => (),
+        }
+
+        let direction = if y > self.cursor_position.y {
+            SearchDirection::Forward
+        } else {
+            SearchDirection::Backward
+        };
+        y = self.visible_row(y, direction);
+        self.document.load_rows(y.saturating_add(1));
+        width = self.document.row(y).map_or(0, Row::len);
+        if vertical {
+            x = self.x_from_render_x(y, desired_column);
+        }
+
+        if x > width {
+            x = width;
+        }
+
+        self.cursor_position = Position { x, y };
+        self.desired_column = vertical.then_some((desired_column, self.cursor_position));
+    }
+
+    /// Goes back to where the cursor was before the last jump, or forward
+    /// again after going back
+    fn jump(&mut self, h: &mut HighLightManager, direction: SearchDirection) {
+        let at = Jump {
+            buffer: self.current_buffer,
+            position: self.cursor_position,
+        };
+        let target = match direction {
+            SearchDirection::Backward => self.jumps.back(at),
+            SearchDirection::Forward => self.jumps.forward(at),
+        };
+        let Some(target) = target.filter(|target| target.buffer < self.buffers.len()) else {
+            let message = match direction {
+                SearchDirection::Backward => "No earlier jump",
+                SearchDirection::Forward => "No later jump",
+            };
+            self.status_message = StatusMessage::from(message.to_string());
+            return;
+        };
+        self.switch_buffer(h, target.buffer);
+        self.document.load_rows(target.position.y.saturating_add(1));
+        self.cursor_position = target.position;
+        self.keep_cursor_in_document();
+    }
+
+    /// Scrolls half a screen up or down, the cursor moving along so it stays
+    /// on the same screen line unless the document ends first
+    fn scroll_half_page(&mut self, direction: SearchDirection) {
+        let half = cmp::max(self.terminal.size().height as usize / 2, 1);
+        let last = self.document.len().saturating_sub(1);
+        let Position { y, .. } = self.cursor_position;
+        let column = self.cursor_render_x();
+        let y = match direction {
+            SearchDirection::Forward => {
+                self.offset.y = cmp::min(self.offset.y.saturating_add(half), last);
+                cmp::min(y.saturating_add(half), last)
+            }
+            SearchDirection::Backward => {
+                self.offset.y = self.offset.y.saturating_sub(half);
+                y.saturating_sub(half)
+            }
+        };
+        let y = self.visible_row(y, direction);
+        self.document.load_rows(y.saturating_add(1));
+        self.cursor_position = Position {
+            x: self.x_from_render_x(y, column),
+            y,
+        };
+    }
+
+    /// Where Up or Down takes the cursor when soft wrapping, a screen line
+    /// up or down in the same column, or `None` at the edge of the document
+    fn wrapped_line_position(&self, key: Key) -> Option<Position> {
+        let Position { y, .. } = self.cursor_position;
+        let height = self.document.len();
+        let text_width = cmp::max(self.text_width(), 1);
+        let render_x = self.cursor_render_x();
+        let render_width = self
+            .document
+            .row(y)
+            .map_or(0, |row| row.render_len(self.tab_stop));
+
+        match key {
+            Key::Up if render_x >= text_width => Some(Position {
+                x: self.x_from_render_x(y, render_x - text_width),
+                y,
+            }),
+            Key::Up if y > 0 => {
+                let y = y - 1;
+                let previous_width = self
+                    .document
+                    .row(y)
+                    .map_or(0, |row| row.render_len(self.tab_stop));
+                let render_x = previous_width - previous_width % text_width + render_x;
+                Some(Position {
+                    x: self.x_from_render_x(y, render_x),
+                    y,
+                })
+            }
+            Key::Down if render_x - render_x % text_width + text_width <= render_width => {
+                Some(Position {
+                    x: self.x_from_render_x(y, render_x + text_width),
+                    y,
+                })
+            }
+            Key::Down if y.saturating_add(1) < height => {
+                let y = y.saturating_add(1);
+                Some(Position {
+                    x: self.x_from_render_x(y, render_x % text_width),
+                    y,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn jump_to_conflict(&mut self, direction: SearchDirection) {
+        match self
+            .document
+            .next_conflict(self.cursor_position.y, direction)
+        {
+            Some(y) => self.cursor_position = Position { x: 0, y },
+            None => {
+                self.status_message = StatusMessage::from("No more merge conflicts".to_string());
+            }
+        }
+    }
+
+    /// Settles the merge conflict at the cursor by keeping our side or
+    /// their side, dropping the other one along with the markers
+    fn resolve_conflict(&mut self, ours: bool) {
+        if self.read_only {
+            self.status_message = StatusMessage::from(READ_ONLY_MESSAGE.to_string());
+            return;
+        }
+        let Some(conflict) = self.document.conflict_at(self.cursor_position.y) else {
+            self.status_message =
+                StatusMessage::from("ERR: The cursor isn't in a merge conflict".to_string());
+            return;
+        };
+        let kept = if ours {
+            conflict.ours()
+        } else {
+            conflict.theirs()
+        };
+        // Removed from the bottom up so the rows above keep their indices
+        let mut edits = Vec::new();
+        for y in (conflict.start..=conflict.end).rev() {
+            if kept.contains(&y) {
+                continue;
+            }
+            let placeholder = self.document.len() == 1;
+            if let Some(text) = self.document.remove_row(y) {
+                edits.push(Edit::RemoveRow {
+                    y,
+                    text,
+                    placeholder,
+                });
+            }
+        }
+        self.history.record(Edit::Group(edits));
+        self.cursor_position = Position {
+            x: 0,
+            y: conflict.start,
+        };
+        self.keep_cursor_in_document();
+    }
+
+    /// Where Home goes from `x` on row `y`: to the indentation first and to
+    /// the start of the row from there, blank rows going straight to the start
+    fn home_x(&self, y: usize, x: usize) -> usize {
+        let Some(row) = self.document.row(y) else {
+            return 0;
+        };
+        let first_non_blank = row.first_non_blank();
+        if x == first_non_blank || row.is_blank() {
+            0
+        } else {
+            first_non_blank
+        }
+    }
+
+    /// Moves to the start of the next or previous line indented no deeper
+    /// than the current one, or to the first or last line when there is none
+    fn jump_to_block(&mut self, direction: SearchDirection) {
+        let y = self
+            .document
+            .next_block(self.cursor_position.y, direction, self.tab_stop)
+            .unwrap_or(match direction {
+                SearchDirection::Forward => self.document.len().saturating_sub(1),
+                SearchDirection::Backward => 0,
+            });
+        self.document.load_rows(y.saturating_add(1));
+        let x = self.document.row(y).map_or(0, Row::first_non_blank);
+        self.cursor_position = Position { x, y };
+    }
+
+    fn x_from_render_x(&self, y: usize, render_x: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(0, |row| row.x_from_render_x(render_x, self.tab_stop))
+    }
+
+    fn draw_welcome_message(
+        &self,
+        out: &mut dyn Write,
+        h: &HighLightManager,
+    ) -> Result<(), std::io::Error> {
+        let welcome_message = format!("Hector editor -- version {VERSION}");
+        let filler = &self.config.filler;
+        let padding = self.text_width().saturating_sub(welcome_message.len()) / 2;
+        let spaces = " ".repeat(padding.saturating_sub(filler.width()));
+        self.draw_filler(out, &format!("{filler}{spaces}{welcome_message}"), h)
+    }
+
+    /// Draws a line past the end of the document, in a faint color on the
+    /// theme's background
+    fn draw_filler(
+        &self,
+        out: &mut dyn Write,
+        text: &str,
+        h: &HighLightManager,
+    ) -> Result<(), std::io::Error> {
+        self.draw_gutter(out, None)?;
+        let width = self.text_width();
+        let text = truncate_to_width(text, width);
+        if let (Some(background), Some(foreground)) = (h.background(), h.dim_foreground()) {
+            let Color { r, g, b, .. } = background;
+            Terminal::set_bg_color(out, color::Rgb(r, g, b))?;
+            let Color { r, g, b, .. } = foreground;
+            Terminal::set_fg_color(out, color::Rgb(r, g, b))?;
+        }
+        write!(
+            out,
+            "{text}{}",
+            " ".repeat(width.saturating_sub(text.width()))
+        )?;
+        Terminal::reset_fg_color(out)?;
+        Terminal::reset_bg_color(out)?;
+        writeln!(out, "\r")
+    }
+
+    fn draw_gutter(
+        &self,
+        out: &mut dyn Write,
+        line_number: Option<usize>,
+    ) -> Result<(), std::io::Error> {
+        let gutter_width = self.gutter_width();
+        if gutter_width == 0 {
+            return Ok(());
+        }
+        Terminal::reset_bg_color(out)?;
+        Terminal::set_fg_color(out, LINE_NUMBER_FG_COLOR)?;
+        let Some(line_number) = line_number else {
+            write!(out, "{}", " ".repeat(gutter_width))?;
+            return Terminal::reset_fg_color(out);
+        };
+        write!(out, "{line_number:>width$}", width = gutter_width - 1)?;
+        #[cfg(feature = "lsp")]
+        if let Some(diagnostic) = self.lsp.diagnostic(self.file_name(), line_number - 1) {
+            // Flag the line in the space after its number
+            Terminal::set_fg_color(out, diagnostic.severity.color())?;
+            write!(out, "{}", diagnostic.severity.marker())?;
+            return Terminal::reset_fg_color(out);
+        }
+        write!(out, " ")?;
+        Terminal::reset_fg_color(out)
+    }
+
+    /// Draws the part of row `y` from screen column `start`, followed by
+    /// how many rows are folded away below it
+    pub fn draw_row(
+        &self,
+        out: &mut dyn Write,
+        y: usize,
+        start: usize,
+        marks: &Marks,
+        background: Option<Color>,
+    ) -> Result<(), std::io::Error> {
+        let Some(row) = self.document.row(y) else {
+            return writeln!(out, "\r");
+        };
+        let width = self.text_width();
+        let end = start + width;
+        let ranges = row.render_highlighted(start, end, self.tab_stop, marks, background);
+        let ranges: Vec<(Style, &str)> = ranges
+            .iter()
+            .map(|(style, text)| (*style, text.as_str()))
+            .collect();
+        let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+        write!(out, "{escaped}")?;
+        let mut used: usize = ranges.iter().map(|(_, text)| text.width()).sum();
+
+        if let Some(hidden) = self.folds.below(y, self.document.len()) {
+            let plural = if hidden.len() == 1 { "" } else { "s" };
+            let summary = truncate_to_width(
+                &format!(" ⋯ {} line{plural}", hidden.len()),
+                width.saturating_sub(used),
+            );
+            if let Some(Color { r, g, b, .. }) = background {
+                Terminal::set_bg_color(out, color::Rgb(r, g, b))?;
+            }
+            Terminal::set_fg_color(out, LINE_NUMBER_FG_COLOR)?;
+            write!(out, "{summary}")?;
+            Terminal::reset_fg_color(out)?;
+            Terminal::reset_bg_color(out)?;
+            used += summary.width();
+        }
+
+        if let Some(Color { r, g, b, .. }) = background {
+            // Tint the rest of the line past the end of the text as well
+            Terminal::set_bg_color(out, color::Rgb(r, g, b))?;
+            write!(out, "{}", " ".repeat(width.saturating_sub(used)))?;
+            Terminal::reset_bg_color(out)?;
+        }
+        writeln!(out, "\r")
+    }
+
+    /// Draws the `line`th of the fuzzy finder results shown at the bottom of
+    /// the screen, scrolled so the selected one stays visible
+    fn draw_finder_line(
+        &self,
+        out: &mut dyn Write,
+        line: usize,
+        finder_height: usize,
+    ) -> Result<(), std::io::Error> {
+        let Some(finder) = &self.finder else {
+            return Ok(());
+        };
+        let width = self.terminal.size().width as usize;
+        let first = finder
+            .selected_index()
+            .saturating_sub(finder_height.saturating_sub(1));
+        let index = first + line;
+        let file = finder.get(index).unwrap_or_default();
+        if index == finder.selected_index() {
+            self.set_status_colors(out)?;
+            let text = truncate_to_width(&format!("> {file}"), width);
+            write!(
+                out,
+                "{text}{}",
+                " ".repeat(width.saturating_sub(text.width()))
+            )?;
+            Terminal::reset_fg_color(out)?;
+            Terminal::reset_bg_color(out)?;
+        } else {
+            write!(out, "{}", truncate_to_width(&format!("  {file}"), width))?;
+        }
+        writeln!(out, "\r")
+    }
+
+    fn draw_rows(&self, out: &mut dyn Write, h: &HighLightManager) -> Result<(), std::io::Error> {
+        let height = self.terminal.size().height as usize;
+        let width = self.text_width();
+        let mut index = self.visible_row(self.offset.y, SearchDirection::Forward);
+        let mut segment = 0;
+        let brackets = self
+            .document
+            .matching_bracket(&self.cursor_position)
+            .map(|matching| [self.cursor_position, matching]);
+        let selection = self.selection();
+        let line_highlight = if self.config.highlight_current_line && !self.prompting {
+            h.line_highlight()
+        } else {
+            None
+        };
+
+        let finder_height = self
+            .finder
+            .as_ref()
+            .map_or(0, |finder| cmp::min(finder.match_count(), height / 2));
+
+        for terminal_row in 0..height {
+            Terminal::clear_current_line(out)?;
+            if let Some(line) = terminal_row.checked_sub(height - finder_height) {
+                self.draw_finder_line(out, line, finder_height)?;
+            } else if let Some(row) = self.document.row(index) {
+                let mut inverted: Vec<usize> = brackets
+                    .iter()
+                    .flatten()
+                    .filter(|position| position.y == index)
+                    .map(|position| position.x)
+                    .collect();
+                inverted.extend(
+                    self.cursors
+                        .iter()
+                        .filter(|position| position.y == index)
+                        .map(|position| position.x),
+                );
+                if let Some((start, end)) =
+                    selection.filter(|(start, end)| (start.y..=end.y).contains(&index))
+                {
+                    let from = if index == start.y { start.x } else { 0 };
+                    let to = if index == end.y { end.x } else { row.len() };
+                    inverted.extend(from..to);
+                }
+                let marks = Marks {
+                    inverted,
+                    matched: self
+                        .search_pattern
+                        .as_ref()
+                        .map(|re| row.find_all_regex(re).into_iter().flatten().collect())
+                        .unwrap_or_default(),
+                    whitespace: if self.config.show_whitespace_errors {
+                        row.whitespace_errors(self.insert_spaces)
+                    } else {
+                        Vec::new()
+                    },
+                };
+                let background = if self.document.is_conflict_marker(index) {
+                    Some(CONFLICT_BG_COLOR)
+                } else {
+                    line_highlight.filter(|_| index == self.cursor_position.y)
+                };
+                if self.soft_wrap {
+                    self.draw_gutter(out, (segment == 0).then(|| index.saturating_add(1)))?;
+                    self.draw_row(out, index, segment * width, &marks, background)?;
+                    segment += 1;
+                    if segment >= self.screen_lines(index) {
+                        index = self.visible_row(index + 1, SearchDirection::Forward);
+                        segment = 0;
+                    }
+                } else {
+                    self.draw_gutter(out, Some(index.saturating_add(1)))?;
+                    self.draw_row(out, index, self.offset.x, &marks, background)?;
+                    index = self.visible_row(index + 1, SearchDirection::Forward);
+                }
+            } else if self.document.is_empty() && terminal_row == height / 3 {
+                self.draw_welcome_message(out, h)?;
+            } else {
+                self.draw_filler(out, &self.config.filler, h)?;
+            }
+        }
+        self.draw_scrollbar(out, height - finder_height)?;
+        if self.config.show_minimap {
+            self.draw_minimap(out, height - finder_height)?;
+        }
+        if self.show_help {
+            self.draw_help(out, height)?;
+        }
+        // Put the cursor back below the rows for the status bar
+        Terminal::cursor_position(out, &Position { x: 0, y: height })
+    }
+
+    /// Draws the keybindings in a box over the middle of the first `rows`
+    /// terminal rows
+    fn draw_help(&self, out: &mut dyn Write, rows: usize) -> Result<(), std::io::Error> {
+        let width = self.terminal.size().width as usize;
+        let bindings = self.keymap.help();
+        let key_width = bindings
+            .iter()
+            .map(|(key, _)| key.width())
+            .max()
+            .unwrap_or(0);
+        let lines: Vec<String> = iter::once("Keybindings, press any key to close".to_string())
+            .chain(iter::once(String::new()))
+            .chain(
+                bindings
+                    .iter()
+                    .map(|(key, action)| format!("{key:<key_width$}  {action}")),
+            )
+            .collect();
+        let box_width = lines
+            .iter()
+            .map(|line| line.width() + 2)
+            .max()
+            .unwrap_or(0)
+            .min(width);
+        let left = width.saturating_sub(box_width) / 2;
+        let top = rows.saturating_sub(lines.len() + 2) / 2;
+        self.set_status_colors(out)?;
+        let padded = iter::once("")
+            .chain(lines.iter().map(String::as_str))
+            .chain(iter::once(""));
+        for (y, line) in padded.take(rows).enumerate() {
+            Terminal::cursor_position(
+                out,
+                &Position {
+                    x: left,
+                    y: top + y,
+                },
+            )?;
+            let text = truncate_to_width(&format!(" {line}"), box_width);
+            write!(out, "{text}{}", " ".repeat(box_width - text.width()))?;
+        }
+        Terminal::reset_fg_color(out)?;
+        Terminal::reset_bg_color(out)
+    }
+
+    /// Draws the scrollbar down the right edge of the first `rows` terminal
+    /// rows, its thumb showing which part of the document is on screen. It
+    /// stays empty while the whole document fits.
+    fn draw_scrollbar(&self, out: &mut dyn Write, rows: usize) -> Result<(), std::io::Error> {
+        let len = self.document.len();
+        let (start, end) = if len > rows && rows > 0 {
+            let size = cmp::max(rows * rows / len, 1);
+            let start = cmp::min(self.offset.y * rows / len, rows - size);
+            (start, start + size)
+        } else {
+            (0, 0)
+        };
+        let x = (self.terminal.size().width as usize).saturating_sub(SCROLLBAR_WIDTH);
+        for y in 0..rows {
+            Terminal::cursor_position(out, &Position { x, y })?;
+            if (start..end).contains(&y) {
+                Terminal::set_bg_color(out, SCROLLBAR_COLOR)?;
+                write!(out, "{}", " ".repeat(SCROLLBAR_WIDTH))?;
+                Terminal::reset_bg_color(out)?;
+            } else {
+                write!(out, "{}", " ".repeat(SCROLLBAR_WIDTH))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the minimap left of the scrollbar down the first `rows`
+    /// terminal rows, each of its lines standing for several document rows
+    /// and the ones on screen shown on a lighter band. Only the first row
+    /// each line stands for is drawn in it, so a frame costs as much however
+    /// big the document is.
+    fn draw_minimap(&self, out: &mut dyn Write, rows: usize) -> Result<(), std::io::Error> {
+        let scale = self.minimap_scale(rows);
+        let on_screen = self.rows_on_screen(rows);
+        let x = (self.terminal.size().width as usize)
+            .saturating_sub(SCROLLBAR_WIDTH)
+            .saturating_sub(MINIMAP_WIDTH);
+        for y in 0..rows {
+            Terminal::cursor_position(out, &Position { x, y })?;
+            let covered = y * scale..(y + 1) * scale;
+            let filled = self.document.row(covered.start).map_or_else(
+                || vec![false; MINIMAP_WIDTH],
+                |row| row.minimap_cells(MINIMAP_WIDTH, MINIMAP_CELL_WIDTH, self.tab_stop),
+            );
+            let in_view = covered.start < on_screen.end && on_screen.start < covered.end;
+            if in_view {
+                Terminal::set_bg_color(out, MINIMAP_VIEW_COLOR)?;
+            }
+            Terminal::set_fg_color(out, MINIMAP_FG_COLOR)?;
+            let line: String = filled
+                .iter()
+                .map(|filled| if *filled { '▬' } else { ' ' })
+                .collect();
+            write!(out, "{line}")?;
+            Terminal::reset_fg_color(out)?;
+            Terminal::reset_bg_color(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.document.file_name.as_deref()
+    }
+
+    /// Shows `warning` in the message bar, for problems found while
+    /// starting up
+    pub fn warn(&mut self, warning: &str) {
+        self.status_message = StatusMessage::from(format!("WARNING: {warning}"));
+    }
+
+    /// Sets up the terminal and opens the files named on the command line
+    pub fn default(arguments: &Arguments) -> Self {
+        let mut warning = None;
+        let config = Config::load().unwrap_or_else(|error| {
+            warning = Some(error);
+            Config::default()
+        });
+        let terminal = Terminal::default().expect("Failed to initialize terminal");
+        Self::with_terminal(arguments, config, terminal, warning)
+    }
+
+    /// An editor on a screen of `width` by `height` cells that leaves the
+    /// real terminal alone, to check what it shows with `snapshot` after
+    /// keys are fed to it with `feed_keys`
+    pub fn headless(arguments: &Arguments, config: Config, width: u16, height: u16) -> Self {
+        let terminal = Terminal::headless(width, height);
+        Self::with_terminal(arguments, config, terminal, None)
+    }
+
+    fn with_terminal(
+        arguments: &Arguments,
+        config: Config,
+        terminal: Terminal,
+        mut warning: Option<String>,
+    ) -> Self {
+        let keymap = Keymap::new(&config.keys).unwrap_or_else(|error| {
+            warning.get_or_insert(error);
+            Keymap::default()
+        });
+        let mut initial_status = warning.map_or_else(
+            || keymap.help_message(),
+            |warning| format!("WARNING: {warning}"),
+        );
+        let mut documents = Vec::new();
+        let read_only = arguments.read_only;
+        let files = &arguments.files;
+        for (file_name, line) in files {
+            match Document::open(file_name) {
+                Ok(doc) => {
+                    if let Some(warning) = open_warning(&doc) {
+                        initial_status = warning;
+                    }
+                    recent_files::add(&[file_name]).ok();
+                    documents.push((doc, *line));
+                }
+                Err(error) => {
+                    initial_status = format!("ERR: Could not open file: {file_name}: {error}");
+                }
+            }
+        }
+        // Piped input is read up front; keys are read from the terminal itself
+        if files.is_empty() && !terminal.is_headless() && !termion::is_tty(&io::stdin()) {
+            match Document::from_reader(io::stdin()) {
+                Ok(document) => documents.push((document, None)),
+                Err(error) => initial_status = format!("ERR: Could not read stdin: {error}"),
+            }
+        }
+        let mut documents = documents.into_iter();
+        let (mut document, line) = documents.next().unwrap_or_default();
+        let cursor_position = start_cursor(&mut document, line);
+        let mut buffers = vec![Buffer::default()];
+        buffers.extend(documents.map(|(mut document, line)| Buffer {
+            cursor_position: start_cursor(&mut document, line),
+            document,
+            read_only,
+            ..Buffer::default()
+        }));
+
+        let mut editor = Self {
+            should_quit: false,
+            terminal,
+            cursor_position,
+            document,
+            offset: Position::default(),
+            status_message: StatusMessage::from(initial_status),
+            show_line_numbers: config.show_line_numbers,
+            soft_wrap: false,
+            prompting: false,
+            read_only,
+            overwrite: false,
+            tab_stop: cmp::max(config.tab_stop, 1),
+            insert_spaces: config.insert_spaces,
+            history: History::default(),
+            folds: Folds::default(),
+            buffers,
+            current_buffer: 0,
+            selection_anchor: None,
+            jumps: JumpList::default(),
+            cursors: Vec::new(),
+            clipboard: None,
+            finder: None,
+            pending_count: None,
+            show_help: false,
+            keymap,
+            search_pattern: None,
+            last_search: None,
+            auto_closed: None,
+            continued: None,
+            desired_column: None,
+            swapped_at: Instant::now(),
+            disk_checked_at: Instant::now(),
+            #[cfg(feature = "lsp")]
+            lsp: lsp::Session::default(),
+            quit_times: config.quit_times,
+            config,
+        };
+        editor.apply_indent();
+        editor.scroll();
+        editor
+    }
+}
+
+/// The comment marker or list bullet `line` starts with, as its length in
+/// bytes and what continues it on the next line, a numbered item going on
+/// with the next number. Markers must be followed by a space or end the line,
+/// so that a `#!` line isn't taken for a comment.
+fn line_marker(line: &str, comment_token: Option<&str>, markdown: bool) -> Option<(usize, String)> {
+    if let Some(token) = comment_token.filter(|token| line.starts_with(*token)) {
+        // Doc comments like `///`, `//!` and `##` are continued as they are
+        let repeated = token.chars().next()?;
+        let rest = line[token.len()..].trim_start_matches(repeated);
+        let rest = if token == "//" {
+            rest.strip_prefix('!').unwrap_or(rest)
+        } else {
+            rest
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        let len = line.len() - rest.trim_start_matches(' ').len();
+        let marker = line[..line.len() - rest.len()].to_string();
+        return Some((len, format!("{marker} ")));
+    }
+    if !markdown {
+        return None;
+    }
+    if let Some(bullet) = ["- ", "* ", "+ "]
+        .iter()
+        .find(|bullet| line.starts_with(**bullet) || line == bullet.trim_end())
+    {
+        return Some((cmp::min(bullet.len(), line.len()), (*bullet).to_string()));
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && line[digits..].starts_with(". ") {
+        let number: usize = line[..digits].parse().ok()?;
+        return Some((digits + 2, format!("{}. ", number.checked_add(1)?)));
+    }
+    None
+}
+
+/// Whether `key`, when no action is bound to it, may take the cursor far
+/// enough for where it was to be remembered in the jump list
+fn is_jump_key(key: Key) -> bool {
+    matches!(
+        key,
+        Key::PageUp | Key::PageDown | Key::CtrlHome | Key::CtrlEnd | Key::CtrlUp | Key::CtrlDown
+    )
+}
+
+/// Whether `key`, when no action is bound to it, changes the document
+fn is_editing_key(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Char(_) | Key::BackTab | Key::Backspace | Key::Delete
+    )
+}
+
+/// Where the cursor starts in `document`: on `line` if one was asked for,
+/// or else where it was left last time
+fn start_cursor(document: &mut Document, line: Option<usize>) -> Position {
+    let Some(line) = line else {
+        return remembered_cursor(document);
+    };
+    let y = cmp::min(line.saturating_sub(1), document.len().saturating_sub(1));
+    document.load_rows(y.saturating_add(1));
+    Position { x: 0, y }
+}
+
+/// `color` made lighter by `amount` when it is dark, or darker when it is light
+fn shade(color: Color, amount: u8) -> Color {
+    let Color { r, g, b, a } = color;
+    let dark = u16::from(r) + u16::from(g) + u16::from(b) < 384;
+    let shade = |channel: u8| {
+        if dark {
+            channel.saturating_add(amount)
+        } else {
+            channel.saturating_sub(amount)
+        }
+    };
+    Color {
+        r: shade(r),
+        g: shade(g),
+        b: shade(b),
+        a,
+    }
+}
+
+/// Where the cursor was left in `document` last time, clamped to its bounds in
+/// case the file has shrunk since
+fn remembered_cursor(document: &mut Document) -> Position {
+    let Some(Position { x, y }) = document.file_name.as_deref().and_then(cursor_history::load)
+    else {
+        return Position::default();
+    };
+    let y = cmp::min(y, document.len().saturating_sub(1));
+    document.load_rows(y.saturating_add(1));
+    let x = cmp::min(x, document.row(y).map_or(0, Row::len));
+    Position { x, y }
+}
+
+/// Message to show about a freshly opened document, if there is anything to say
+fn open_warning(document: &Document) -> Option<String> {
+    if document.is_lossy() && !document.is_binary() {
+        return Some(
+            "WARNING: Not valid UTF-8, the invalid bytes are shown as \u{fffd}".to_string(),
+        );
+    }
+    document.has_mixed_line_endings().then(|| {
+        format!(
+            "WARNING: Mixed line endings, they will be saved as {}",
+            document.line_ending().name()
+        )
+    })
+}
+
+/// The longest prefix of `text` that fits in `width` columns, never cutting
+/// a grapheme in half
+fn truncate_to_width(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        used += grapheme.width();
+        if used > width {
+            break;
+        }
+        result.push_str(grapheme);
+    }
+    result
+}
+
+fn matching_themes<'a>(h: &'a HighLightManager, query: &str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    h.theme_names()
+        .into_iter()
+        .filter(|name| name.to_lowercase().contains(&query))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncating_keeps_text_that_fits() {
+        assert_eq!(truncate_to_width("abc", 3), "abc");
+        assert_eq!(truncate_to_width("日本語", 6), "日本語");
+        assert_eq!(truncate_to_width("", 0), "");
+    }
+
+    #[test]
+    fn truncating_never_splits_a_wide_character() {
+        assert_eq!(truncate_to_width("日本語", 5), "日本");
+        assert_eq!(truncate_to_width("日本語", 1), "");
+        assert_eq!(truncate_to_width("a日b", 2), "a");
+    }
+
+    #[test]
+    fn truncating_never_splits_a_cluster() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(
+            truncate_to_width(&format!("a{family}b"), 3),
+            format!("a{family}")
+        );
+        assert_eq!(truncate_to_width(&format!("a{family}b"), 2), "a");
+        assert_eq!(truncate_to_width("👍🏽👍🏽", 3), "👍🏽");
+        assert_eq!(truncate_to_width("🇫🇷🇯🇵", 3), "🇫🇷");
+        // A combining accent stays with its letter
+        assert_eq!(truncate_to_width("e\u{301}e\u{301}", 1), "e\u{301}");
+    }
+
+    #[test]
+    fn numbered_items_go_on_with_the_next_number() {
+        assert_eq!(
+            line_marker("9. x", None, true),
+            Some((3, "10. ".to_string()))
+        );
+        // The last number there is isn't continued
+        assert_eq!(line_marker("18446744073709551615. x", None, true), None);
+        assert_eq!(line_marker("99999999999999999999. x", None, true), None);
+    }
 }
@@ -0,0 +1,121 @@
+use std::fs;
+use std::path::Path;
+
+/// How many directories deep to look for files
+const MAX_DEPTH: usize = 8;
+/// Stop walking once this many files have been found
+const MAX_FILES: usize = 10_000;
+
+/// The files under a directory, narrowed down by a fuzzy query
+pub struct Finder {
+    files: Vec<String>,
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+impl Finder {
+    pub fn new(root: &Path) -> Self {
+        let mut files = Vec::new();
+        walk(root, None, 0, &mut files);
+        files.sort();
+        Self::from_files(files)
+    }
+
+    /// Picks among `files`, kept in their given order until filtered
+    pub fn from_files(files: Vec<String>) -> Self {
+        let matches = (0..files.len()).collect();
+        Self {
+            files,
+            matches,
+            selected: 0,
+        }
+    }
+
+    /// Keeps the files matching `query`, best matches first
+    pub fn filter(&mut self, query: &str) {
+        let mut scored: Vec<(usize, usize)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| Some((fuzzy_score(file, query)?, index)))
+            .collect();
+        scored.sort_by_key(|(score, index)| (*score, self.files[*index].len()));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+        self.selected = 0;
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.get(self.selected)
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    /// The `index`th best match
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let index = *self.matches.get(index)?;
+        Some(&self.files[index])
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+}
+
+/// Collects the files below `directory` as paths relative to the root,
+/// skipping hidden entries and not following symlinked directories
+fn walk(directory: &Path, prefix: Option<&str>, depth: usize, files: &mut Vec<String>) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = match prefix {
+            Some(prefix) => format!("{prefix}/{name}"),
+            None => name.to_string(),
+        };
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                walk(&entry.path(), Some(&path), depth + 1, files);
+            }
+            Ok(_) => files.push(path),
+            Err(_) => (),
+        }
+    }
+}
+
+/// Matches `query` as a subsequence of `candidate`, ignoring case. The score
+/// is the number of characters skipped between matched ones, so lower is
+/// better, or `None` when `candidate` doesn't match at all.
+fn fuzzy_score(candidate: &str, query: &str) -> Option<usize> {
+    let mut score = 0;
+    let mut candidate = candidate.chars().flat_map(char::to_lowercase);
+    for wanted in query.chars().flat_map(char::to_lowercase) {
+        let skipped = candidate.position(|c| c == wanted)?;
+        score += skipped;
+    }
+    Some(score)
+}
@@ -0,0 +1,57 @@
+use std::ops::Range;
+
+/// The collapsed regions of a document, each hiding the rows of a `Range`
+/// below the row that stays visible for it. Folds only hold for as many
+/// lines as the document had when they were made, adding or removing
+/// lines opens them all.
+#[derive(Default)]
+pub struct Folds {
+    ranges: Vec<Range<usize>>,
+    len: usize,
+}
+
+impl Folds {
+    /// Collapses `hidden`, replacing the folds inside it
+    pub fn fold(&mut self, hidden: Range<usize>, len: usize) {
+        if len != self.len {
+            self.ranges.clear();
+            self.len = len;
+        }
+        self.ranges
+            .retain(|range| range.end <= hidden.start || range.start >= hidden.end);
+        self.ranges.push(hidden);
+    }
+
+    /// Opens the fold shown as row `y`, returning whether there was one
+    pub fn unfold(&mut self, y: usize, len: usize) -> bool {
+        let Some(range) = self.below(y, len).cloned() else {
+            return false;
+        };
+        self.ranges.retain(|other| *other != range);
+        true
+    }
+
+    /// Opens the folds hiding row `y`, so it can be shown
+    pub fn reveal(&mut self, y: usize) {
+        self.ranges.retain(|range| !range.contains(&y));
+    }
+
+    /// The rows hidden below row `y`
+    pub fn below(&self, y: usize, len: usize) -> Option<&Range<usize>> {
+        let start = y.checked_add(1)?;
+        self.valid(len).iter().find(|range| range.start == start)
+    }
+
+    /// The fold hiding row `y`, if it is hidden
+    pub fn hiding(&self, y: usize, len: usize) -> Option<&Range<usize>> {
+        self.valid(len).iter().find(|range| range.contains(&y))
+    }
+
+    fn valid(&self, len: usize) -> &[Range<usize>] {
+        if len == self.len {
+            &self.ranges
+        } else {
+            &[]
+        }
+    }
+}
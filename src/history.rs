@@ -0,0 +1,183 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Document;
+use crate::Position;
+
+pub enum Edit {
+    Insert {
+        at: Position,
+        text: String,
+    },
+    Delete {
+        at: Position,
+        text: String,
+    },
+    Newline {
+        at: Position,
+        indent: usize,
+    },
+    Replace {
+        at: Position,
+        query: String,
+        replacement: String,
+    },
+    InsertRow {
+        y: usize,
+        text: String,
+    },
+    RemoveRow {
+        y: usize,
+        text: String,
+        /// Whether it was the only row, an empty one taking its place since
+        /// a document always has one
+        placeholder: bool,
+    },
+    /// Several edits undone and redone as one, like the trimming done on save
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    fn apply(&self, document: &mut Document) -> Position {
+        match self {
+            Self::Insert { at, text } => document.insert_text(at, text),
+            Self::Delete { at, text } => {
+                for _ in text.graphemes(true) {
+                    document.delete(at);
+                }
+                *at
+            }
+            Self::Newline { at, .. } => {
+                let x = document.insert_newline(at);
+                Position {
+                    x,
+                    y: at.y.saturating_add(1),
+                }
+            }
+            Self::Replace {
+                at,
+                query,
+                replacement,
+            } => {
+                document.replace(at, query, replacement);
+                *at
+            }
+            Self::InsertRow { y, text } => {
+                document.insert_row(*y, text);
+                Position { x: 0, y: *y }
+            }
+            Self::RemoveRow { y, .. } => {
+                document.remove_row(*y);
+                Position { x: 0, y: *y }
+            }
+            Self::Group(edits) => edits
+                .iter()
+                .fold(Position::default(), |_, edit| edit.apply(document)),
+        }
+    }
+
+    fn revert(&self, document: &mut Document) -> Position {
+        match self {
+            Self::Insert { at, text } => {
+                for _ in text.graphemes(true) {
+                    document.delete(at);
+                }
+                *at
+            }
+            Self::Delete { at, text } => {
+                document.insert_text(at, text);
+                *at
+            }
+            Self::Newline { at, indent } => {
+                document.delete(at);
+                for _ in 0..*indent {
+                    document.delete(at);
+                }
+                *at
+            }
+            Self::Replace {
+                at,
+                query,
+                replacement,
+            } => {
+                document.replace(at, replacement, query);
+                *at
+            }
+            Self::InsertRow { y, .. } => {
+                document.remove_row(*y);
+                Position { x: 0, y: *y }
+            }
+            Self::RemoveRow {
+                y,
+                text,
+                placeholder,
+            } => {
+                document.insert_row(*y, text);
+                if *placeholder {
+                    document.remove_row(y.saturating_add(1));
+                }
+                Position { x: 0, y: *y }
+            }
+            Self::Group(edits) => edits
+                .iter()
+                .rev()
+                .fold(Position::default(), |_, edit| edit.revert(document)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+
+        // Consecutive characters typed on the same line are merged into a
+        // single undo unit, broken up after whitespace so undo goes word by word
+        if let (
+            Some(Edit::Insert { at, text }),
+            Edit::Insert {
+                at: new_at,
+                text: new_text,
+            },
+        ) = (self.undo.last_mut(), &edit)
+        {
+            let contiguous = at.y == new_at.y
+                && !text.contains('\n')
+                && at.x.saturating_add(text.graphemes(true).count()) == new_at.x;
+            let word_finished =
+                text.ends_with(char::is_whitespace) && !new_text.starts_with(char::is_whitespace);
+            if contiguous && !word_finished {
+                text.push_str(new_text);
+                return;
+            }
+        }
+
+        self.undo.push(edit);
+    }
+
+    pub fn undo(&mut self, document: &mut Document) -> Option<Position> {
+        let edit = self.undo.pop()?;
+        let position = edit.revert(document);
+        self.redo.push(edit);
+        Some(position)
+    }
+
+    pub fn redo(&mut self, document: &mut Document) -> Option<Position> {
+        let edit = self.redo.pop()?;
+        let position = edit.apply(document);
+        self.undo.push(edit);
+        Some(position)
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_count(&self) -> usize {
+        self.redo.len()
+    }
+}
@@ -0,0 +1,60 @@
+use crate::Position;
+
+/// How many jumps to remember, the oldest being forgotten first
+const MAX_JUMPS: usize = 100;
+
+/// A place the cursor jumped from or to
+#[derive(Clone, Copy, PartialEq)]
+pub struct Jump {
+    pub buffer: usize,
+    pub position: Position,
+}
+
+/// The places the cursor jumped away from, to go back and forth between
+/// them like a browser's history
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// Where going back and forward has got to, `jumps.len()` until going
+    /// back
+    current: usize,
+}
+
+impl JumpList {
+    /// Remembers `from` as left by a jump, forgetting the jumps gone back
+    /// over
+    pub fn push(&mut self, from: Jump) {
+        self.jumps.truncate(self.current);
+        if self.jumps.last() != Some(&from) {
+            self.jumps.push(from);
+        }
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.current = self.jumps.len();
+    }
+
+    /// The place jumped from before, with `at` remembered to go forward to
+    pub fn back(&mut self, at: Jump) -> Option<Jump> {
+        if self.current == 0 {
+            return None;
+        }
+        if self.current == self.jumps.len() {
+            self.jumps.push(at);
+        } else {
+            self.jumps[self.current] = at;
+        }
+        self.current -= 1;
+        Some(self.jumps[self.current])
+    }
+
+    /// The place gone back from, with `at` remembered to go back to again
+    pub fn forward(&mut self, at: Jump) -> Option<Jump> {
+        if self.current + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.jumps[self.current] = at;
+        self.current += 1;
+        Some(self.jumps[self.current])
+    }
+}
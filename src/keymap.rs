@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+/// A command run by a key, which the config file can bind to another one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Save,
+    Open,
+    FindFile,
+    OpenRecent,
+    Command,
+    NextBuffer,
+    Find,
+    NextMatch,
+    PreviousMatch,
+    Replace,
+    GoToLine,
+    Undo,
+    Redo,
+    Cut,
+    Copy,
+    Paste,
+    AddCursor,
+    DuplicateLine,
+    MoveLineUp,
+    MoveLineDown,
+    ToggleComment,
+    HalfPageUp,
+    HalfPageDown,
+    JumpBack,
+    JumpForward,
+    ToggleFold,
+    NextConflict,
+    PreviousConflict,
+    ToggleOverwrite,
+    ToggleLineNumbers,
+    ToggleSoftWrap,
+    NextTheme,
+    PickTheme,
+    Help,
+    Quit,
+}
+
+/// Every action, by the name the `[keys]` table of the config file uses,
+/// with the key it is on until bound to another one
+const DEFAULT_BINDINGS: &[(&str, Action, Key)] = &[
+    ("save", Action::Save, Key::Ctrl('s')),
+    ("open", Action::Open, Key::Ctrl('o')),
+    ("find_file", Action::FindFile, Key::Ctrl('p')),
+    ("open_recent", Action::OpenRecent, Key::Alt('o')),
+    ("command", Action::Command, Key::Ctrl('e')),
+    // Ctrl-Space
+    ("next_buffer", Action::NextBuffer, Key::Null),
+    ("find", Action::Find, Key::Ctrl('f')),
+    ("next_match", Action::NextMatch, Key::Alt('n')),
+    ("previous_match", Action::PreviousMatch, Key::Alt('N')),
+    ("replace", Action::Replace, Key::Ctrl('r')),
+    ("go_to_line", Action::GoToLine, Key::Ctrl('g')),
+    ("undo", Action::Undo, Key::Ctrl('z')),
+    ("redo", Action::Redo, Key::Ctrl('y')),
+    ("cut", Action::Cut, Key::Ctrl('k')),
+    ("copy", Action::Copy, Key::Ctrl('c')),
+    ("paste", Action::Paste, Key::Ctrl('v')),
+    ("add_cursor", Action::AddCursor, Key::Ctrl('d')),
+    ("duplicate_line", Action::DuplicateLine, Key::Alt('d')),
+    ("move_line_up", Action::MoveLineUp, Key::AltUp),
+    ("move_line_down", Action::MoveLineDown, Key::AltDown),
+    // Ctrl-/ arrives as Ctrl-7
+    ("toggle_comment", Action::ToggleComment, Key::Ctrl('7')),
+    ("half_page_up", Action::HalfPageUp, Key::Ctrl('u')),
+    ("half_page_down", Action::HalfPageDown, Key::Ctrl('l')),
+    ("jump_back", Action::JumpBack, Key::AltLeft),
+    ("jump_forward", Action::JumpForward, Key::AltRight),
+    ("toggle_fold", Action::ToggleFold, Key::Alt('f')),
+    ("next_conflict", Action::NextConflict, Key::Alt('c')),
+    ("previous_conflict", Action::PreviousConflict, Key::Alt('C')),
+    ("toggle_overwrite", Action::ToggleOverwrite, Key::Insert),
+    (
+        "toggle_line_numbers",
+        Action::ToggleLineNumbers,
+        Key::Ctrl('n'),
+    ),
+    ("toggle_soft_wrap", Action::ToggleSoftWrap, Key::Ctrl('w')),
+    ("next_theme", Action::NextTheme, Key::Ctrl('t')),
+    ("pick_theme", Action::PickTheme, Key::Alt('t')),
+    ("help", Action::Help, Key::Ctrl('h')),
+    ("quit", Action::Quit, Key::Ctrl('q')),
+];
+
+/// A line of the Ctrl-H help
+enum HelpRow {
+    /// Actions sharing a line, shown with the keys they are bound to
+    Bound(&'static [Action], &'static str),
+    /// Keys that can't be bound to something else, like the arrows
+    Fixed(&'static str, &'static str),
+}
+
+const HELP: &[HelpRow] = &[
+    HelpRow::Bound(&[Action::Save], "save"),
+    HelpRow::Bound(&[Action::Open], "open a file"),
+    HelpRow::Bound(&[Action::FindFile], "find a file"),
+    HelpRow::Bound(&[Action::OpenRecent], "reopen a recent file"),
+    HelpRow::Bound(&[Action::Command], "run a command"),
+    HelpRow::Bound(&[Action::NextBuffer], "switch to the next buffer"),
+    HelpRow::Bound(&[Action::Find], "find"),
+    HelpRow::Bound(
+        &[Action::NextMatch, Action::PreviousMatch],
+        "next / previous match",
+    ),
+    HelpRow::Bound(&[Action::Replace], "replace"),
+    HelpRow::Bound(&[Action::GoToLine], "go to line"),
+    HelpRow::Bound(&[Action::Undo, Action::Redo], "undo / redo"),
+    HelpRow::Bound(
+        &[Action::Cut, Action::Copy, Action::Paste],
+        "cut / copy / paste",
+    ),
+    HelpRow::Fixed("Shift-Arrows", "select"),
+    HelpRow::Bound(&[Action::AddCursor], "add a cursor at the next occurrence"),
+    HelpRow::Bound(&[Action::DuplicateLine], "duplicate the line"),
+    HelpRow::Bound(
+        &[Action::MoveLineUp, Action::MoveLineDown],
+        "move the line up / down",
+    ),
+    HelpRow::Fixed("Tab / Shift-Tab", "indent / dedent"),
+    HelpRow::Bound(&[Action::ToggleComment], "comment / uncomment"),
+    HelpRow::Fixed("Ctrl-Left / Ctrl-Right", "previous / next word"),
+    HelpRow::Fixed("Ctrl-Up / Ctrl-Down", "previous / next block"),
+    HelpRow::Fixed("Ctrl-Home / Ctrl-End", "start / end of the document"),
+    HelpRow::Bound(
+        &[Action::HalfPageUp, Action::HalfPageDown],
+        "half a page up / down",
+    ),
+    HelpRow::Bound(
+        &[Action::JumpBack, Action::JumpForward],
+        "back / forward through jumps",
+    ),
+    HelpRow::Fixed("Alt-Digits, then a move", "repeat the move"),
+    HelpRow::Bound(&[Action::ToggleFold], "fold / unfold the block"),
+    HelpRow::Bound(
+        &[Action::NextConflict, Action::PreviousConflict],
+        "next / previous merge conflict",
+    ),
+    HelpRow::Bound(&[Action::ToggleOverwrite], "toggle overwrite mode"),
+    HelpRow::Bound(&[Action::ToggleLineNumbers], "toggle line numbers"),
+    HelpRow::Bound(&[Action::ToggleSoftWrap], "toggle soft wrap"),
+    HelpRow::Bound(
+        &[Action::NextTheme, Action::PickTheme],
+        "next theme / pick a theme",
+    ),
+    HelpRow::Bound(&[Action::Help], "show this help"),
+    HelpRow::Bound(&[Action::Quit], "quit"),
+];
+
+/// The actions named in the message bar when the editor starts
+const HELP_MESSAGE: &[(Action, &str)] = &[
+    (Action::Help, "help"),
+    (Action::Save, "save"),
+    (Action::Find, "find"),
+    (Action::Replace, "replace"),
+    (Action::GoToLine, "go to line"),
+    (Action::Quit, "quit"),
+];
+
+impl Action {
+    /// Whether the action changes the document, which read-only mode
+    /// refuses
+    pub fn edits(self) -> bool {
+        matches!(
+            self,
+            Self::Cut
+                | Self::Paste
+                | Self::Replace
+                | Self::Undo
+                | Self::Redo
+                | Self::ToggleComment
+                | Self::DuplicateLine
+                | Self::MoveLineUp
+                | Self::MoveLineDown
+        )
+    }
+
+    /// Whether the action may take the cursor far enough for where it was
+    /// to be remembered in the jump list
+    pub fn jumps(self) -> bool {
+        matches!(
+            self,
+            Self::HalfPageUp
+                | Self::HalfPageDown
+                | Self::GoToLine
+                | Self::Find
+                | Self::Command
+                | Self::NextMatch
+                | Self::PreviousMatch
+                | Self::NextConflict
+                | Self::PreviousConflict
+        )
+    }
+
+    fn named(name: &str) -> Option<Self> {
+        DEFAULT_BINDINGS
+            .iter()
+            .find(|(action_name, ..)| *action_name == name)
+            .map(|&(_, action, _)| action)
+    }
+}
+
+/// Which key runs which action, read by both the key handling and the
+/// Ctrl-H help so that they always agree
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|&(_, action, key)| (key, action))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// The default bindings with `overrides`, from action names to key
+    /// names like `Ctrl-X` or `Alt-Shift-X`, moving each of those actions
+    /// to its new key and taking that key away from whatever it ran before
+    pub fn new(overrides: &HashMap<String, String>) -> Result<Self, String> {
+        let mut overrides: Vec<(&String, &String)> = overrides.iter().collect();
+        overrides.sort();
+        let mut keymap = Self::default();
+        let mut overridden: Vec<(Key, &str)> = Vec::new();
+        for (name, key_name) in overrides {
+            let action =
+                Action::named(name).ok_or_else(|| format!("Unknown action {name:?} in [keys]"))?;
+            let key = parse_key(key_name)
+                .ok_or_else(|| format!("Invalid key {key_name:?} for {name} in [keys]"))?;
+            if let Some((_, other)) = overridden.iter().find(|(bound, _)| *bound == key) {
+                return Err(format!(
+                    "{key_name} is bound to both {other} and {name} in [keys]"
+                ));
+            }
+            overridden.push((key, name));
+            keymap
+                .bindings
+                .retain(|&(bound, bound_action)| bound != key && bound_action != action);
+            keymap.bindings.push((key, action));
+        }
+        Ok(keymap)
+    }
+
+    /// The action `key` runs, if any
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|&(_, action)| action)
+    }
+
+    fn key_name(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, bound_action)| *bound_action == action)
+            .map(|&(key, _)| key_name(key))
+    }
+
+    /// The lines of the Ctrl-H help, as the keys and what they do
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        HELP.iter()
+            .map(|row| match row {
+                HelpRow::Bound(actions, description) => {
+                    let keys: Vec<String> = actions
+                        .iter()
+                        .map(|&action| {
+                            self.key_name(action)
+                                .unwrap_or_else(|| "(unbound)".to_string())
+                        })
+                        .collect();
+                    (keys.join(" / "), *description)
+                }
+                HelpRow::Fixed(keys, description) => ((*keys).to_string(), *description),
+            })
+            .collect()
+    }
+
+    /// The message bar shown when the editor starts, with the keys of the
+    /// most used actions
+    pub fn help_message(&self) -> String {
+        let hints: Vec<String> = HELP_MESSAGE
+            .iter()
+            .filter_map(|&(action, description)| {
+                Some(format!("{} = {description}", self.key_name(action)?))
+            })
+            .collect();
+        format!("HELP: {}", hints.join(" | "))
+    }
+}
+
+/// How the help and the config file write `key`
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Null => "Ctrl-Space".to_string(),
+        Key::Ctrl('7') => "Ctrl-/".to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c.to_ascii_uppercase()),
+        Key::Alt(c) if c.is_ascii_uppercase() => format!("Alt-Shift-{c}"),
+        Key::Alt(c) => format!("Alt-{}", c.to_ascii_uppercase()),
+        Key::AltLeft => "Alt-Left".to_string(),
+        Key::AltRight => "Alt-Right".to_string(),
+        Key::AltUp => "Alt-Up".to_string(),
+        Key::AltDown => "Alt-Down".to_string(),
+        Key::Insert => "Insert".to_string(),
+        Key::F(n) => format!("F{n}"),
+        key => format!("{key:?}"),
+    }
+}
+
+/// The key written as `name`, which has to be one `key_name` gives. Plain
+/// characters are left for typing, and Ctrl-I, Ctrl-J and Ctrl-M for the
+/// Tab and Enter keys they arrive as.
+fn parse_key(name: &str) -> Option<Key> {
+    let letter = |rest: &str| {
+        let mut chars = rest.chars();
+        let c = chars.next().filter(char::is_ascii_alphabetic)?;
+        chars.next().is_none().then_some(c)
+    };
+    let key = match name {
+        "Ctrl-Space" => Key::Null,
+        "Ctrl-/" => Key::Ctrl('7'),
+        "Alt-Left" => Key::AltLeft,
+        "Alt-Right" => Key::AltRight,
+        "Alt-Up" => Key::AltUp,
+        "Alt-Down" => Key::AltDown,
+        "Insert" => Key::Insert,
+        _ => {
+            if let Some(c) = name.strip_prefix("Ctrl-").and_then(letter) {
+                let c = c.to_ascii_lowercase();
+                if matches!(c, 'i' | 'j' | 'm') {
+                    return None;
+                }
+                Key::Ctrl(c)
+            } else if let Some(c) = name.strip_prefix("Alt-Shift-").and_then(letter) {
+                Key::Alt(c.to_ascii_uppercase())
+            } else if let Some(c) = name.strip_prefix("Alt-").and_then(letter) {
+                Key::Alt(c.to_ascii_lowercase())
+            } else {
+                let n: u8 = name.strip_prefix('F')?.parse().ok()?;
+                if !(1..=12).contains(&n) {
+                    return None;
+                }
+                Key::F(n)
+            }
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(overrides: &[(&str, &str)]) -> Result<Keymap, String> {
+        let overrides = overrides
+            .iter()
+            .map(|&(action, key)| (action.to_string(), key.to_string()))
+            .collect();
+        Keymap::new(&overrides)
+    }
+
+    #[test]
+    fn key_names_parse_back_to_their_keys() {
+        for &(_, _, key) in DEFAULT_BINDINGS {
+            assert_eq!(parse_key(&key_name(key)), Some(key));
+        }
+        assert_eq!(parse_key("F5"), Some(Key::F(5)));
+        assert_eq!(parse_key("ctrl-x"), None);
+        assert_eq!(parse_key("Ctrl-M"), None);
+        assert_eq!(parse_key("x"), None);
+        assert_eq!(parse_key("F13"), None);
+    }
+
+    #[test]
+    fn an_override_moves_the_action_and_frees_its_key() {
+        let keymap = keymap(&[("save", "Ctrl-X"), ("quit", "Ctrl-S")]).unwrap();
+        assert_eq!(keymap.action(Key::Ctrl('x')), Some(Action::Save));
+        assert_eq!(keymap.action(Key::Ctrl('s')), Some(Action::Quit));
+        assert_eq!(keymap.action(Key::Ctrl('q')), None);
+        let help = keymap.help();
+        assert!(help.contains(&("Ctrl-X".to_string(), "save")));
+        assert!(help.contains(&("Ctrl-S".to_string(), "quit")));
+        assert!(keymap
+            .help_message()
+            .starts_with("HELP: Ctrl-H = help | Ctrl-X = save"));
+    }
+
+    #[test]
+    fn an_action_whose_key_is_taken_shows_as_unbound() {
+        let keymap = keymap(&[("quit", "Ctrl-S")]).unwrap();
+        assert!(keymap.help().contains(&("(unbound)".to_string(), "save")));
+        assert!(!keymap.help_message().contains("= save"));
+    }
+
+    #[test]
+    fn rejects_unknown_actions_keys_and_clashes() {
+        assert!(keymap(&[("sav", "Ctrl-X")]).is_err());
+        assert!(keymap(&[("save", "Ctrl-Shift-X")]).is_err());
+        assert!(keymap(&[("save", "Ctrl-X"), ("quit", "Ctrl-X")]).is_err());
+    }
+
+    #[test]
+    fn default_help_matches_the_default_keys() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            keymap.help_message(),
+            "HELP: Ctrl-H = help | Ctrl-S = save | Ctrl-F = find | Ctrl-R = replace | Ctrl-G = go to line | Ctrl-Q = quit"
+        );
+        assert!(keymap
+            .help()
+            .contains(&("Alt-N / Alt-Shift-N".to_string(), "next / previous match")));
+    }
+}
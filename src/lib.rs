@@ -0,0 +1,40 @@
+//! A small terminal text editor with syntax highlighting.
+//!
+//! `Document` and `Row` hold and edit text without needing a terminal, so
+//! they can be embedded on their own. `Editor` drives them in a `Terminal`.
+#![warn(clippy::all, clippy::pedantic)]
+#![allow(
+    clippy::must_use_candidate,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc
+)]
+mod arguments;
+mod assets;
+mod command;
+mod config;
+mod cursor_history;
+mod document;
+mod editor;
+mod finder;
+mod folds;
+mod history;
+mod jumps;
+mod keymap;
+#[cfg(feature = "lsp")]
+mod lsp;
+mod recent_files;
+mod row;
+mod terminal;
+
+pub use arguments::{Arguments, Invocation, USAGE};
+pub use assets::{load_syntaxes, load_themes};
+pub use config::{Config, Rgb};
+pub use document::{Conflict, Document, Indent, LineEnding};
+pub use editor::Editor;
+pub use editor::HighLightManager;
+pub use editor::HighlightingState;
+pub use editor::Position;
+pub use editor::SearchDirection;
+pub use editor::VERSION;
+pub use row::{Marks, Row};
+pub use terminal::{Size, Terminal};
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use serde_json::{json, Value};
+use termion::color;
+
+use crate::Document;
+use crate::Position;
+
+/// A language server to start for files of a syntax
+struct Server {
+    syntax: &'static str,
+    language_id: &'static str,
+    command: &'static [&'static str],
+}
+
+const SERVERS: &[Server] = &[
+    Server {
+        syntax: "Rust",
+        language_id: "rust",
+        command: &["rust-analyzer"],
+    },
+    Server {
+        syntax: "Python",
+        language_id: "python",
+        command: &["pylsp"],
+    },
+    Server {
+        syntax: "Go",
+        language_id: "go",
+        command: &["gopls"],
+    },
+    Server {
+        syntax: "C",
+        language_id: "c",
+        command: &["clangd"],
+    },
+    Server {
+        syntax: "C++",
+        language_id: "cpp",
+        command: &["clangd"],
+    },
+    Server {
+        syntax: "JavaScript",
+        language_id: "javascript",
+        command: &["typescript-language-server", "--stdio"],
+    },
+];
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// The letter flagging a line in the gutter
+    pub fn marker(self) -> char {
+        match self {
+            Self::Error => 'E',
+            Self::Warning => 'W',
+            Self::Information => 'I',
+            Self::Hint => 'H',
+        }
+    }
+
+    pub fn color(self) -> color::Rgb {
+        match self {
+            Self::Error => color::Rgb(220, 50, 47),
+            Self::Warning => color::Rgb(181, 137, 0),
+            Self::Information => color::Rgb(38, 139, 210),
+            Self::Hint => color::Rgb(128, 128, 128),
+        }
+    }
+
+    fn from_lsp(severity: Option<u64>) -> Self {
+        match severity {
+            Some(2) => Self::Warning,
+            Some(3) => Self::Information,
+            Some(4) => Self::Hint,
+            _ => Self::Error,
+        }
+    }
+}
+
+pub struct Diagnostic {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What the thread reading the server's output passes on
+enum Incoming {
+    Initialized,
+    Request(Value),
+    Diagnostics(String, Vec<Diagnostic>),
+}
+
+/// A running language server, talked to over its stdin and stdout
+struct Client {
+    command: &'static [&'static str],
+    language_id: &'static str,
+    child: Child,
+    stdin: ChildStdin,
+    incoming: Receiver<Incoming>,
+    initialized: bool,
+    /// The revision last sent for every document opened in the server
+    opened: HashMap<String, usize>,
+}
+
+impl Client {
+    fn start(server: &Server) -> io::Result<Self> {
+        let mut child = Command::new(server.command[0])
+            .args(&server.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(io::Error::other("language server has no stdio"));
+        };
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || read_messages(BufReader::new(stdout), &sender));
+
+        let root = env::current_dir().ok().and_then(|dir| uri(&dir));
+        let mut client = Self {
+            command: server.command,
+            language_id: server.language_id,
+            child,
+            stdin,
+            incoming,
+            initialized: false,
+            opened: HashMap::new(),
+        };
+        client.send(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "processId": std::process::id(),
+                "rootUri": root,
+                "capabilities": {
+                    "textDocument": { "publishDiagnostics": {} },
+                },
+            },
+        }))?;
+        Ok(client)
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.stdin.flush()
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    /// Sends the whole text of `document` if it changed since last time
+    fn sync(&mut self, uri: &str, document: &mut Document) -> io::Result<()> {
+        let revision = document.revision();
+        let last = self.opened.get(uri).copied();
+        if !self.initialized || last == Some(revision) {
+            return Ok(());
+        }
+        let text = document.text_between(
+            &Position::default(),
+            &Position {
+                x: usize::MAX,
+                y: usize::MAX,
+            },
+        );
+        if last.is_some() {
+            self.notify(
+                "textDocument/didChange",
+                &json!({
+                    "textDocument": { "uri": uri, "version": revision },
+                    "contentChanges": [{ "text": text }],
+                }),
+            )?;
+        } else {
+            self.notify(
+                "textDocument/didOpen",
+                &json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": self.language_id,
+                        "version": revision,
+                        "text": text,
+                    },
+                }),
+            )?;
+        }
+        self.opened.insert(uri.to_string(), revision);
+        Ok(())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.notify("exit", &Value::Null).ok();
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// The language server for the current file, along with the diagnostics it
+/// published
+#[derive(Default)]
+pub struct Session {
+    client: Option<Client>,
+    /// Servers that couldn't be started, so they aren't tried again
+    failed: Vec<&'static [&'static str]>,
+    diagnostics: HashMap<String, Vec<Diagnostic>>,
+    /// The URI of each file synced, so that it isn't looked up on disk again
+    /// for every line drawn
+    uris: HashMap<String, String>,
+}
+
+impl Session {
+    /// Makes sure the server for `syntax` is running and knows the latest
+    /// contents of `document`, and takes in what it sent in the meantime
+    pub fn sync(&mut self, syntax: &str, document: &mut Document) {
+        self.receive();
+        let Some(file_name) = document.file_name.as_deref() else {
+            return;
+        };
+        if !self.uris.contains_key(file_name) {
+            let Some(uri) = uri(Path::new(file_name)) else {
+                return;
+            };
+            self.uris.insert(file_name.to_string(), uri);
+        }
+        let uri = self.uris[file_name].clone();
+        let Some(server) = SERVERS.iter().find(|server| server.syntax == syntax) else {
+            return;
+        };
+        if self.failed.contains(&server.command) {
+            return;
+        }
+        if self
+            .client
+            .as_ref()
+            .is_none_or(|client| client.command != server.command)
+        {
+            self.client = None;
+            let Ok(client) = Client::start(server) else {
+                self.failed.push(server.command);
+                return;
+            };
+            self.client = Some(client);
+        }
+        if let Some(client) = self.client.as_mut() {
+            if client.sync(&uri, document).is_err() {
+                // The server went away
+                self.failed.push(client.command);
+                self.client = None;
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        while let Ok(incoming) = client.incoming.try_recv() {
+            match incoming {
+                Incoming::Initialized => {
+                    client.initialized = true;
+                    client.notify("initialized", &json!({})).ok();
+                }
+                // Requests from the server get an empty answer, they are
+                // about features this client doesn't have
+                Incoming::Request(id) => {
+                    let response = json!({ "jsonrpc": "2.0", "id": id, "result": null });
+                    client.send(&response).ok();
+                }
+                Incoming::Diagnostics(uri, diagnostics) => {
+                    self.diagnostics.insert(uri, diagnostics);
+                }
+            }
+        }
+    }
+
+    /// The most severe diagnostic on line `y` of `file_name`, once it has
+    /// been synced
+    pub fn diagnostic(&self, file_name: Option<&str>, y: usize) -> Option<&Diagnostic> {
+        let uri = self.uris.get(file_name?)?;
+        self.diagnostics
+            .get(uri)?
+            .iter()
+            .filter(|diagnostic| diagnostic.line == y)
+            .min_by(|a, b| {
+                a.severity
+                    .partial_cmp(&b.severity)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+}
+
+/// Parses the messages the server writes, each preceded by headers giving
+/// its length, until the server closes its output
+fn read_messages(mut reader: BufReader<impl Read>, sender: &Sender<Incoming>) {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+            continue;
+        };
+        let incoming = match (message.get("method"), message.get("id")) {
+            (Some(method), _) if method == "textDocument/publishDiagnostics" => {
+                parse_diagnostics(&message["params"])
+            }
+            (Some(_), Some(id)) => Some(Incoming::Request(id.clone())),
+            (None, Some(id)) if *id == 1 => Some(Incoming::Initialized),
+            _ => None,
+        };
+        if let Some(incoming) = incoming {
+            if sender.send(incoming).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn parse_diagnostics(params: &Value) -> Option<Incoming> {
+    let uri = params["uri"].as_str()?.to_string();
+    let diagnostics = params["diagnostics"]
+        .as_array()?
+        .iter()
+        .filter_map(|diagnostic| {
+            let line = diagnostic["range"]["start"]["line"].as_u64()?;
+            Some(Diagnostic {
+                line: usize::try_from(line).ok()?,
+                severity: Severity::from_lsp(diagnostic["severity"].as_u64()),
+                message: diagnostic["message"].as_str()?.lines().next()?.to_string(),
+            })
+        })
+        .collect();
+    Some(Incoming::Diagnostics(uri, diagnostics))
+}
+
+/// The `file://` URI of `path`, with the bytes that aren't allowed in one
+/// percent-encoded
+fn uri(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mut uri = String::from("file://");
+    for byte in path.to_str()?.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            write!(uri, "%{byte:02X}").ok();
+        }
+    }
+    Some(uri)
+}
@@ -1,23 +1,43 @@
 #![warn(clippy::all, clippy::pedantic)]
-mod document;
-mod editor;
-mod row;
-mod terminal;
 
-use std::env;
-use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
-
-pub use document::Document;
-pub use editor::Editor;
-pub use editor::HighLightManager;
-pub use editor::Position;
-pub use row::Row;
-pub use terminal::Terminal;
+use editore::{load_syntaxes, load_themes, Editor, HighLightManager, Invocation, USAGE, VERSION};
+use std::{env, process};
 
 fn main() {
-    let ts = ThemeSet::load_defaults();
-    let ps = SyntaxSet::load_defaults_newlines();
-    let mut hm = HighLightManager::default(&ps, &ts);
+    // Handled before the terminal is put in raw mode
+    let arguments = match Invocation::parse(&env::args().collect::<Vec<String>>()) {
+        Ok(Invocation::Edit(arguments)) => arguments,
+        Ok(Invocation::Help) => {
+            print!("{USAGE}");
+            return;
+        }
+        Ok(Invocation::Version) => {
+            println!("editore {VERSION}");
+            return;
+        }
+        Err(error) => {
+            eprintln!("editore: {error}\nTry 'editore --help' for more information.");
+            process::exit(2);
+        }
+    };
+    let (ts, mut warnings) = load_themes();
+    let (ps, syntax_warnings) = load_syntaxes();
+    warnings.extend(syntax_warnings);
+    let mut editor = Editor::default(&arguments);
+    if let Some(warning) = warnings.first() {
+        match warnings.len() {
+            1 => editor.warn(warning),
+            count => editor.warn(&format!("{warning} (and {} more)", count - 1)),
+        }
+    }
+    let mut hm = HighLightManager::default(&ps, &ts, editor.file_name());
 
-    Editor::default(env::args().collect()).run(&mut hm);
+    let result = editor.run(&mut hm);
+    // Leave raw mode before reporting anything or exiting, as `process::exit`
+    // doesn't run destructors
+    drop(editor);
+    if let Err(error) = result {
+        eprintln!("editore: {error}");
+        process::exit(1);
+    }
 }
@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::config;
+
+/// How many recently opened files to remember
+const MAX_ENTRIES: usize = 50;
+/// Where the list is kept in the state directory, as absolute paths so the
+/// files can be reopened from any directory
+const STATE_FILE: &str = "recent";
+
+/// The files opened most recently, latest first, leaving out those that no
+/// longer exist. Files under the current directory are given relative to it.
+pub fn load() -> Vec<String> {
+    let Some(contents) =
+        config::state_file(STATE_FILE).and_then(|path| fs::read_to_string(path).ok())
+    else {
+        return Vec::new();
+    };
+    let current_dir = env::current_dir().ok();
+    contents
+        .lines()
+        .filter(|path| Path::new(path).is_file())
+        .map(|path| {
+            current_dir
+                .as_deref()
+                .and_then(|dir| Path::new(path).strip_prefix(dir).ok())
+                .and_then(Path::to_str)
+                .unwrap_or(path)
+                .to_string()
+        })
+        .collect()
+}
+
+/// Moves the given files to the top of the list, the last one ending up
+/// first
+pub fn add(file_names: &[&str]) -> Result<(), String> {
+    let Some(path) = config::state_file(STATE_FILE) else {
+        return Ok(());
+    };
+    let contents = fs::read_to_string(&path).unwrap_or_default();
+    let mut entries: Vec<String> = contents.lines().map(str::to_string).collect();
+    for file_name in file_names {
+        let Some(key) = config::state_key(file_name) else {
+            continue;
+        };
+        entries.retain(|entry| *entry != key);
+        entries.insert(0, key);
+    }
+    entries.truncate(MAX_ENTRIES);
+
+    let mut contents = entries.join("\n");
+    contents.push('\n');
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|error| format!("Could not create {}: {error}", directory.display()))?;
+    }
+    fs::write(&path, contents)
+        .map_err(|error| format!("Could not write {}: {error}", path.display()))
+}
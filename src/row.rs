@@ -1,17 +1,42 @@
 use std::cmp;
+use std::convert::TryFrom;
+use std::ops::Range;
 
+use regex::Regex;
+use syntect::highlighting::{Color, FontStyle, Style};
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+use crate::{HighLightManager, HighlightingState, SearchDirection};
+
+/// A line of text, indexed by grapheme clusters
+#[derive(Default)]
 pub struct Row {
     string: String,
     len: usize,
+    highlighting: Vec<(Style, String)>,
+    highlight_state: Option<HighlightingState>,
+    /// The line key and starting state `highlighting` was made from, so
+    /// the row is only highlighted again when either changes
+    highlighted_from: Option<(u64, HighlightingState)>,
+}
+
+/// Graphemes of a row drawn with other colors than the syntax ones, by index
+#[derive(Default)]
+pub struct Marks {
+    /// Drawn with their colors swapped, like the selection
+    pub inverted: Vec<usize>,
+    /// Search matches
+    pub matched: Vec<usize>,
+    /// Whitespace that shouldn't be there, drawn on a warning background
+    pub whitespace: Vec<usize>,
 }
 
 impl From<&str> for Row {
     fn from(slice: &str) -> Self {
         let mut row = Self {
             string: String::from(slice),
-            len: 0,
+            ..Self::default()
         };
 
         row.update_len();
@@ -20,25 +45,149 @@ impl From<&str> for Row {
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    /// The screen columns `start..end` of the row. Graphemes are never
+    /// split: flags, emoji with modifiers and letters with combining accents
+    /// are drawn whole or, when cut off by either end, as spaces.
+    pub fn render(&self, start: usize, end: usize, tab_stop: usize) -> String {
         let mut result = String::new();
+        let mut column = 0;
+
+        for grapheme in self.string[..].graphemes(true) {
+            if column >= end {
+                break;
+            }
+            column += render_grapheme(grapheme, column, start, end, tab_stop, &mut result);
+        }
+        result
+    }
 
-        for grapheme in self.string[..]
+    /// Renders the syntax highlighted row, drawing the graphemes in `marks`
+    /// over the syntax colors. A `background` replaces the one picked by the
+    /// theme while keeping the token colors.
+    pub fn render_highlighted(
+        &self,
+        start: usize,
+        end: usize,
+        tab_stop: usize,
+        marks: &Marks,
+        background: Option<Color>,
+    ) -> Vec<(Style, String)> {
+        let mut result = Vec::new();
+        let mut column = 0;
+        let mut index = 0;
+
+        for (style, text) in &self.highlighting {
+            let style = &background.map_or(*style, |background| Style {
+                background,
+                ..*style
+            });
+            let mut rendered = String::new();
+            for grapheme in text[..].graphemes(true) {
+                let special = if marks.inverted.contains(&index) {
+                    Some(inverted(*style))
+                } else if marks.matched.contains(&index) {
+                    Some(MATCH_STYLE)
+                } else if marks.whitespace.contains(&index) {
+                    Some(Style {
+                        background: WHITESPACE_BG_COLOR,
+                        ..*style
+                    })
+                } else if control_notation(grapheme).is_some() {
+                    Some(dimmed(*style))
+                } else {
+                    None
+                };
+                if let Some(special) = special {
+                    if !rendered.is_empty() {
+                        result.push((*style, rendered));
+                    }
+                    let mut cell = String::new();
+                    column += render_grapheme(grapheme, column, start, end, tab_stop, &mut cell);
+                    if !cell.is_empty() {
+                        result.push((special, cell));
+                    }
+                    rendered = String::new();
+                } else {
+                    column +=
+                        render_grapheme(grapheme, column, start, end, tab_stop, &mut rendered);
+                }
+                index += 1;
+            }
+            if !rendered.is_empty() {
+                result.push((*style, rendered));
+            }
+            if column >= end {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Converts a grapheme index into the screen column it is rendered at
+    pub fn render_x(&self, x: usize, tab_stop: usize) -> usize {
+        self.string[..]
             .graphemes(true)
-            .skip(start)
-            .take(end - start)
+            .take(x)
+            .fold(0, |column, grapheme| {
+                column + grapheme_width(grapheme, column, tab_stop)
+            })
+    }
+
+    /// Converts a screen column back into the index of the grapheme covering it
+    pub fn x_from_render_x(&self, render_x: usize, tab_stop: usize) -> usize {
+        let mut column = 0;
+        for (x, grapheme) in self.string[..].graphemes(true).enumerate() {
+            column += grapheme_width(grapheme, column, tab_stop);
+            if column > render_x {
+                return x;
+            }
+        }
+        self.len
+    }
+
+    /// The screen columns grapheme `x` is rendered over, a single one right
+    /// after the text for the end of the row
+    pub fn render_range(&self, x: usize, tab_stop: usize) -> Range<usize> {
+        let start = self.render_x(x, tab_stop);
+        let width = self
+            .grapheme(x)
+            .map_or(1, |grapheme| grapheme_width(grapheme, start, tab_stop));
+        start..start + width
+    }
+
+    pub fn render_len(&self, tab_stop: usize) -> usize {
+        self.render_x(self.len, tab_stop)
+    }
+
+    pub fn highlight(&mut self, h: &HighLightManager, state: &mut HighlightingState) {
+        let key = h.line_key(&self.string);
+        if let (Some((highlighted_key, start)), Some(end)) =
+            (&self.highlighted_from, &self.highlight_state)
         {
-            if grapheme == "\t" {
-                result.push_str("  ")
-            } else {
-                result.push_str(grapheme);
+            if *highlighted_key == key && start == state {
+                state.clone_from(end);
+                return;
             }
         }
-        result
+        let start = state.clone();
+        self.highlighting = h.highlight_line(&self.string, state);
+        self.highlight_state = Some(state.clone());
+        self.highlighted_from = Some((key, start));
+    }
+
+    /// Draws the whole row in `style`, skipping syntax highlighting
+    pub fn highlight_plain(&mut self, style: Style) {
+        self.highlighting = vec![(style, self.string.clone())];
+        self.highlight_state = None;
+        self.highlighted_from = None;
+    }
+
+    pub fn highlight_state(&self) -> Option<&HighlightingState> {
+        self.highlight_state.as_ref()
     }
 
+    /// How many grapheme clusters the row holds, which is how far the
+    /// cursor can go in it
     pub fn len(&self) -> usize {
         self.len
     }
@@ -47,7 +196,518 @@ impl Row {
         self.len == 0
     }
 
+    pub fn insert(&mut self, at: usize, c: char) {
+        self.insert_str(at, c.encode_utf8(&mut [0; 4]));
+    }
+
+    pub fn insert_str(&mut self, at: usize, string: &str) {
+        let index = self.byte_index(at);
+        self.string.insert_str(index, string);
+        self.update_len();
+    }
+
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        let end = cmp::max(start, end);
+        self.string[self.byte_index(start)..self.byte_index(end)].to_string()
+    }
+
+    /// Which of `cells` cells, `cell_width` screen columns each, have
+    /// something other than whitespace drawn in them, to show the row
+    /// shrunk down in the minimap
+    pub fn minimap_cells(&self, cells: usize, cell_width: usize, tab_stop: usize) -> Vec<bool> {
+        let mut filled = vec![false; cells];
+        let mut column = 0;
+        for grapheme in self.render(0, cells * cell_width, tab_stop).graphemes(true) {
+            if !grapheme.chars().all(char::is_whitespace) {
+                if let Some(cell) = filled.get_mut(column / cell_width) {
+                    *cell = true;
+                }
+            }
+            column += grapheme.width();
+        }
+        filled
+    }
+
+    /// Index of the first grapheme that isn't whitespace, or the row length
+    /// when the row is blank
+    pub fn first_non_blank(&self) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .position(|grapheme| !grapheme.chars().all(char::is_whitespace))
+            .unwrap_or(self.len)
+    }
+
+    /// The indices of whitespace at the end of the row and of indentation
+    /// mixing tabs and spaces, the way git flags them: spaces before a tab,
+    /// or any tab when indenting with spaces
+    pub fn whitespace_errors(&self, insert_spaces: bool) -> Vec<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let indent = self.first_non_blank();
+        let last_tab = graphemes[..indent].iter().rposition(|g| *g == "\t");
+        let text_end = graphemes
+            .iter()
+            .rposition(|g| !g.chars().all(char::is_whitespace))
+            .map_or(0, |x| x + 1);
+        (0..self.len)
+            .filter(|x| {
+                let misplaced = if insert_spaces {
+                    graphemes[*x] == "\t"
+                } else {
+                    graphemes[*x] == " " && last_tab.is_some_and(|tab| *x < tab)
+                };
+                *x >= text_end || (*x < indent && misplaced)
+            })
+            .collect()
+    }
+
+    /// Whether the row holds nothing but whitespace
+    pub fn is_blank(&self) -> bool {
+        self.first_non_blank() == self.len
+    }
+
+    pub fn grapheme(&self, at: usize) -> Option<&str> {
+        self.string[..].graphemes(true).nth(at)
+    }
+
+    pub fn delete(&mut self, at: usize) {
+        if at >= self.len() {
+            return;
+        }
+        self.replace(at, 1, "");
+    }
+
+    pub fn append(&mut self, new: &Self) {
+        self.string.push_str(&new.string);
+        self.update_len();
+    }
+
+    #[must_use]
+    pub fn split(&mut self, at: usize) -> Self {
+        let remainder = self.string.split_off(self.byte_index(at));
+        self.update_len();
+        Self::from(&remainder[..])
+    }
+
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len || query.is_empty() {
+            return None;
+        }
+        let start = if direction == SearchDirection::Forward {
+            at
+        } else {
+            0
+        };
+        let end = if direction == SearchDirection::Forward {
+            self.len
+        } else {
+            at
+        };
+        let substring: String = self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end - start)
+            .collect();
+        let matching_byte_index = if direction == SearchDirection::Forward {
+            substring.find(query)
+        } else {
+            substring.rfind(query)
+        }?;
+        substring
+            .grapheme_indices(true)
+            .position(|(byte_index, _)| byte_index == matching_byte_index)
+            .map(|grapheme_index| start + grapheme_index)
+    }
+
+    /// Like `find`, but for the first non-empty match of `re`
+    pub fn find_regex(&self, re: &Regex, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len {
+            return None;
+        }
+        let at = self.byte_index(at);
+        let mut matches = re
+            .find_iter(&self.string)
+            .map(|found| found.range())
+            .filter(|range| !range.is_empty());
+        let start = if direction == SearchDirection::Forward {
+            matches.find(|range| range.start >= at)
+        } else {
+            matches.take_while(|range| range.start < at).last()
+        }?
+        .start;
+        Some(self.string[..start].graphemes(true).count())
+    }
+
+    /// The grapheme ranges of every non-empty match of `re`
+    pub fn find_all_regex(&self, re: &Regex) -> Vec<Range<usize>> {
+        let boundaries: Vec<usize> = self
+            .string
+            .grapheme_indices(true)
+            .map(|(byte_index, _)| byte_index)
+            .collect();
+        let grapheme_index =
+            |byte_index: usize| boundaries.partition_point(|boundary| *boundary < byte_index);
+        re.find_iter(&self.string)
+            .map(|found| found.range())
+            .filter(|range| !range.is_empty())
+            .map(|range| grapheme_index(range.start)..grapheme_index(range.end))
+            .collect()
+    }
+
+    /// Replaces the `len` graphemes starting at `at` with `replacement`
+    pub fn replace(&mut self, at: usize, len: usize, replacement: &str) {
+        let range = self.byte_index(at)..self.byte_index(at.saturating_add(len));
+        self.string.replace_range(range, replacement);
+        self.update_len();
+    }
+
+    /// Removes trailing whitespace, returning it if there was any
+    pub fn trim_end(&mut self) -> Option<String> {
+        let len = self.string.trim_end().len();
+        if len == self.string.len() {
+            return None;
+        }
+        let trimmed = self.string.split_off(len);
+        self.update_len();
+        Some(trimmed)
+    }
+
+    /// Index of the start of the word following `at`, or the end of the row
+    pub fn next_word_start(&self, at: usize) -> usize {
+        let classes: Vec<CharClass> = self.string[..].graphemes(true).map(char_class).collect();
+        let mut x = at;
+        if let Some(class) = classes.get(x) {
+            while x < classes.len() && classes[x] == *class && *class != CharClass::Whitespace {
+                x += 1;
+            }
+        }
+        while x < classes.len() && classes[x] == CharClass::Whitespace {
+            x += 1;
+        }
+        cmp::min(x, self.len)
+    }
+
+    /// Index of the start of the word preceding `at`, or the start of the row
+    pub fn previous_word_start(&self, at: usize) -> usize {
+        let classes: Vec<CharClass> = self.string[..].graphemes(true).map(char_class).collect();
+        let mut x = cmp::min(at, classes.len());
+        while x > 0 && classes[x - 1] == CharClass::Whitespace {
+            x -= 1;
+        }
+        if let Some(class) = x.checked_sub(1).map(|index| classes[index]) {
+            while x > 0 && classes[x - 1] == class {
+                x -= 1;
+            }
+        }
+        x
+    }
+
+    /// The graphemes of the word at `at`, or of the one just before when
+    /// `at` is right after a word
+    pub fn word_at(&self, at: usize) -> Option<Range<usize>> {
+        let classes: Vec<CharClass> = self.string[..].graphemes(true).map(char_class).collect();
+        let is_word = |x: usize| classes.get(x) == Some(&CharClass::Word);
+        let mut start = if is_word(at) {
+            at
+        } else {
+            at.checked_sub(1).filter(|x| is_word(*x))?
+        };
+        let mut end = start + 1;
+        while start > 0 && is_word(start - 1) {
+            start -= 1;
+        }
+        while is_word(end) {
+            end += 1;
+        }
+        Some(start..end)
+    }
+
+    /// Number of words, counted the way word motion steps over them with
+    /// runs of punctuation being words of their own
+    pub fn word_count(&self) -> usize {
+        let classes: Vec<CharClass> = self.string[..].graphemes(true).map(char_class).collect();
+        (0..classes.len())
+            .filter(|x| {
+                classes[*x] != CharClass::Whitespace && (*x == 0 || classes[x - 1] != classes[*x])
+            })
+            .count()
+    }
+
+    /// Looks for `target` from `at` onwards (or before `at` when searching
+    /// backward), skipping over pairs nested inside by `bracket`. `depth`
+    /// carries the nesting level over from the rows already searched.
+    pub fn find_bracket(
+        &self,
+        bracket: &str,
+        target: &str,
+        at: usize,
+        direction: SearchDirection,
+        depth: &mut usize,
+    ) -> Option<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let at = cmp::min(at, graphemes.len());
+        let indices: Vec<usize> = if direction == SearchDirection::Forward {
+            (at..graphemes.len()).collect()
+        } else {
+            (0..at).rev().collect()
+        };
+        for x in indices {
+            if graphemes[x] == bracket {
+                *depth += 1;
+            } else if graphemes[x] == target {
+                if *depth == 0 {
+                    return Some(x);
+                }
+                *depth -= 1;
+            }
+        }
+        None
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.string.as_bytes()
+    }
+
+    /// Byte offset of the grapheme at index `at`, or the end of the string
+    /// when `at` is past the last grapheme
+    fn byte_index(&self, at: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+
     fn update_len(&mut self) {
         self.len = self.string[..].graphemes(true).count();
     }
 }
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    Whitespace,
+    Punctuation,
+    Word,
+}
+
+fn char_class(grapheme: &str) -> CharClass {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Whitespace,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Punctuation,
+    }
+}
+
+/// How search matches are drawn, dark text on amber
+const MATCH_STYLE: Style = Style {
+    foreground: Color {
+        r: 0x28,
+        g: 0x28,
+        b: 0x28,
+        a: 0xff,
+    },
+    background: Color {
+        r: 0xe5,
+        g: 0xb5,
+        b: 0x67,
+        a: 0xff,
+    },
+    font_style: FontStyle::empty(),
+};
+
+/// The background of whitespace errors, a muted red
+const WHITESPACE_BG_COLOR: Color = Color {
+    r: 0x8b,
+    g: 0x2a,
+    b: 0x2a,
+    a: 0xff,
+};
+
+/// `style` with its foreground faded halfway into the background
+fn dimmed(style: Style) -> Style {
+    let (foreground, background) = (style.foreground, style.background);
+    Style {
+        foreground: Color {
+            r: u8::midpoint(foreground.r, background.r),
+            g: u8::midpoint(foreground.g, background.g),
+            b: u8::midpoint(foreground.b, background.b),
+            a: foreground.a,
+        },
+        ..style
+    }
+}
+
+fn inverted(style: Style) -> Style {
+    Style {
+        foreground: style.background,
+        background: style.foreground,
+        ..style
+    }
+}
+
+/// Number of terminal columns `grapheme` takes up when drawn at `column`,
+/// with wide characters like CJK ideographs and emoji counting as two
+fn grapheme_width(grapheme: &str, column: usize, tab_stop: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_stop = cmp::max(tab_stop, 1);
+        tab_stop - column % tab_stop
+    } else if let Some(notation) = control_notation(grapheme) {
+        notation.len()
+    } else {
+        cmp::max(grapheme.width(), 1)
+    }
+}
+
+/// How a control character other than a tab is shown, in caret notation
+/// like `^M` or as its code like `<85>`, so it can't mess with the terminal
+fn control_notation(grapheme: &str) -> Option<String> {
+    let c = grapheme
+        .chars()
+        .next()
+        .filter(|c| c.is_control() && *c != '\t')?;
+    let code = u32::from(c);
+    Some(match u8::try_from(code) {
+        Ok(byte) if byte < 0x20 || byte == 0x7f => format!("^{}", char::from(byte ^ 0x40)),
+        _ => format!("<{code:x}>"),
+    })
+}
+
+/// Pushes the part of `grapheme` that falls within the `start..end` columns
+/// onto `result`, returning how many columns the grapheme takes up
+fn render_grapheme(
+    grapheme: &str,
+    column: usize,
+    start: usize,
+    end: usize,
+    tab_stop: usize,
+    result: &mut String,
+) -> usize {
+    let width = grapheme_width(grapheme, column, tab_stop);
+    if grapheme != "\t" && column >= start && column + width <= end {
+        match control_notation(grapheme) {
+            Some(notation) => result.push_str(&notation),
+            None => result.push_str(grapheme),
+        }
+    } else {
+        // Tabs and wide characters cut off by the edge of the screen are
+        // padded with spaces so the following columns stay aligned
+        let visible = (column..column + width)
+            .filter(|column| *column >= start && *column < end)
+            .count();
+        result.push_str(&" ".repeat(visible));
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMBINING: &str = "e\u{301}"; // é as e and a combining acute accent
+    const FAMILY: &str = "👨\u{200d}👩\u{200d}👧"; // joined with ZWJs
+    const THUMBS_UP: &str = "👍🏽"; // with a skin tone modifier
+    const FLAG: &str = "🇫🇷"; // two regional indicators
+
+    #[test]
+    fn len_counts_grapheme_clusters() {
+        assert_eq!(Row::from(COMBINING).len(), 1);
+        assert_eq!(Row::from(FAMILY).len(), 1);
+        assert_eq!(Row::from(THUMBS_UP).len(), 1);
+        assert_eq!(Row::from(FLAG).len(), 1);
+        assert_eq!(Row::from("日本語").len(), 3);
+        assert_eq!(Row::from(&format!("a{COMBINING}{FAMILY}b")[..]).len(), 4);
+    }
+
+    #[test]
+    fn render_measures_display_columns() {
+        assert_eq!(Row::from(COMBINING).render_len(4), 1);
+        assert_eq!(Row::from(FAMILY).render_len(4), 2);
+        assert_eq!(Row::from(THUMBS_UP).render_len(4), 2);
+        assert_eq!(Row::from(FLAG).render_len(4), 2);
+        assert_eq!(Row::from("日本語").render_len(4), 6);
+    }
+
+    #[test]
+    fn render_keeps_clusters_whole() {
+        let row = Row::from(&format!("{COMBINING}{FAMILY}日x")[..]);
+        assert_eq!(row.render(0, 6, 4), format!("{COMBINING}{FAMILY}日x"));
+        assert_eq!(row.render(1, 5, 4), format!("{FAMILY}日"));
+        assert_eq!(row.render(0, 1, 4), COMBINING);
+    }
+
+    #[test]
+    fn render_pads_clusters_cut_by_the_edges() {
+        let row = Row::from(&format!("a{FAMILY}日b")[..]);
+        // The family emoji takes columns 1 and 2, 日 columns 3 and 4
+        assert_eq!(row.render(2, 5, 4), " 日");
+        assert_eq!(row.render(4, 6, 4), " b");
+        assert_eq!(row.render(0, 2, 4), "a ");
+        assert_eq!(row.render(0, 4, 4), format!("a{FAMILY} "));
+    }
+
+    #[test]
+    fn cursor_moves_between_display_columns_and_clusters() {
+        let row = Row::from(&format!("a{COMBINING}{THUMBS_UP}日b")[..]);
+        let columns: Vec<usize> = (0..=row.len()).map(|x| row.render_x(x, 4)).collect();
+        assert_eq!(columns, [0, 1, 2, 4, 6, 7]);
+        // Either column of a wide cluster is covered by it
+        assert_eq!(row.x_from_render_x(2, 4), 2);
+        assert_eq!(row.x_from_render_x(3, 4), 2);
+        assert_eq!(row.x_from_render_x(5, 4), 3);
+        assert_eq!(row.x_from_render_x(9, 4), row.len());
+        assert_eq!(row.render_range(3, 4), 4..6);
+        assert_eq!(row.grapheme(1), Some(COMBINING));
+        assert_eq!(row.slice(1, 3), format!("{COMBINING}{THUMBS_UP}"));
+    }
+
+    #[test]
+    fn insert_goes_between_clusters() {
+        let mut row = Row::from(&format!("{COMBINING}日{FAMILY}")[..]);
+        row.insert(1, 'x');
+        row.insert(3, 'y');
+        row.insert(row.len(), 'z');
+        assert_eq!(row.as_str(), format!("{COMBINING}x日y{FAMILY}z"));
+        assert_eq!(row.len(), 6);
+        // Past the end appends
+        row.insert(99, '!');
+        assert_eq!(row.as_str(), format!("{COMBINING}x日y{FAMILY}z!"));
+    }
+
+    #[test]
+    fn delete_removes_whole_clusters() {
+        let mut row = Row::from(&format!("a{COMBINING}{THUMBS_UP}日")[..]);
+        row.delete(1);
+        assert_eq!(row.as_str(), format!("a{THUMBS_UP}日"));
+        row.delete(1);
+        assert_eq!(row.as_str(), "a日");
+        row.delete(2);
+        assert_eq!(row.as_str(), "a日");
+        row.delete(1);
+        assert_eq!(row.as_str(), "a");
+        assert_eq!(row.len(), 1);
+    }
+
+    #[test]
+    fn split_at_a_cluster_boundary() {
+        let mut row = Row::from(&format!("日本{FLAG}{COMBINING}")[..]);
+        let rest = row.split(2);
+        assert_eq!(row.as_str(), "日本");
+        assert_eq!(row.len(), 2);
+        assert_eq!(rest.as_str(), format!("{FLAG}{COMBINING}"));
+        assert_eq!(rest.len(), 2);
+        let mut row = Row::from(FAMILY);
+        assert!(row.split(1).is_empty());
+        assert_eq!(row.as_str(), FAMILY);
+    }
+
+    #[test]
+    fn append_keeps_the_length_in_sync() {
+        let mut row = Row::from("日本");
+        row.append(&Row::from(&format!("{THUMBS_UP}{COMBINING}")[..]));
+        assert_eq!(row.as_str(), format!("日本{THUMBS_UP}{COMBINING}"));
+        assert_eq!(row.len(), 4);
+        assert_eq!(row.render_len(4), 7);
+    }
+}
@@ -1,87 +1,358 @@
 use crate::Position;
+use std::collections::VecDeque;
 use std::io::{self, stdout, Write};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use termion::{
     color,
-    event::Key,
-    input::TermRead,
+    event::{Event, Key},
+    input::{Events, MouseTerminal, TermRead},
     raw::{IntoRawMode, RawTerminal},
+    AsyncReader,
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The most base64 a clipboard escape sequence may carry, since terminals
+/// drop longer ones
+const CLIPBOARD_LIMIT: usize = 100_000;
+/// What terminals send before and after pasted text once bracketed paste
+/// is turned on, which termion leaves as unsupported events
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// The terminal in raw mode, reading keys and mouse events from it. A
+/// headless one reads queued keys instead and draws nowhere.
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    events: Option<Events<AsyncReader>>,
+    _stdout: Option<MouseTerminal<RawTerminal<std::io::Stdout>>>,
+    queued: VecDeque<Key>,
 }
 
 impl Terminal {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, std::io::Error> {
         let size = termion::terminal_size()?;
+        let raw = stdout().into_raw_mode()?;
+        // Bracketed paste, so pasted text can be told from typing
+        write!(stdout(), "\x1b[?2004h")?;
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2),
             },
-            _stdout: stdout().into_raw_mode()?,
+            events: Some(termion::async_stdin().events()),
+            _stdout: Some(MouseTerminal::from(raw)),
+            queued: VecDeque::new(),
         })
     }
 
+    /// A terminal of `width` by `height` cells that leaves the real one
+    /// alone, for rendering frames into strings
+    pub fn headless(width: u16, height: u16) -> Self {
+        Self {
+            size: Size {
+                width,
+                height: height.saturating_sub(2),
+            },
+            events: None,
+            _stdout: None,
+            queued: VecDeque::new(),
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.events.is_none()
+    }
+
+    /// Where frames are drawn, nowhere for a headless terminal
+    pub fn output(&self) -> Box<dyn Write> {
+        if self.is_headless() {
+            Box::new(io::sink())
+        } else {
+            Box::new(stdout())
+        }
+    }
+
+    /// Adds keys for a headless terminal to read as if they were typed
+    pub fn queue_keys(&mut self, keys: &[Key]) {
+        self.queued.extend(keys);
+    }
+
+    pub fn has_queued_keys(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
     pub fn size(&self) -> &Size {
         &self.size
     }
 
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+    /// Re-reads the terminal dimensions, returning whether they changed
+    pub fn update_size(&mut self) -> Result<bool, std::io::Error> {
+        if self.is_headless() {
+            return Ok(false);
+        }
+        let (width, height) = termion::terminal_size()?;
+        let height = height.saturating_sub(2);
+        if width == self.size.width && height == self.size.height {
+            return Ok(false);
+        }
+        self.size = Size { width, height };
+        Ok(true)
     }
 
-    pub fn cursor_position(position: &Position) {
+    pub fn clear_screen(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", termion::clear::All)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn cursor_position(out: &mut dyn Write, position: &Position) -> Result<(), std::io::Error> {
         let Position { x, y } = position;
         let x = x.saturating_add(1);
         let y = y.saturating_add(1);
         let x = x as u16;
         let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+        write!(out, "{}", termion::cursor::Goto(x, y))
+    }
+
+    pub fn flush(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        out.flush()
+    }
+
+    /// Blocks until a key is pressed, discarding any other events. Reads
+    /// interrupted by a signal are retried, only other errors are returned.
+    /// A headless terminal out of queued keys reads Esc, so a prompt waiting
+    /// for more is cancelled.
+    pub fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        let Some(events) = self.events.as_mut() else {
+            return Ok(self.queued.pop_front().unwrap_or(Key::Esc));
+        };
+        loop {
+            match events.next() {
+                Some(Ok(Event::Key(key))) => return Ok(key),
+                Some(Err(error)) if !is_transient(&error) => return Err(error),
+                Some(_) => (),
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
     }
 
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    /// Reads the text pasted after `PASTE_START` up to the end of the paste,
+    /// line breaks as `\n`
+    pub fn read_paste(&mut self) -> Result<String, std::io::Error> {
+        let mut text = String::new();
+        let Some(events) = self.events.as_mut() else {
+            return Ok(text);
+        };
+        loop {
+            match events.next() {
+                Some(Ok(Event::Key(Key::Char(c)))) => text.push(c),
+                Some(Ok(Event::Unsupported(bytes))) if bytes == PASTE_END => return Ok(text),
+                Some(Err(error)) if !is_transient(&error) => return Err(error),
+                Some(_) => (),
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
+    /// Waits at most `timeout` for a key press or mouse event
+    pub fn read_event_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Event>, std::io::Error> {
+        let Some(events) = self.events.as_mut() else {
+            return Ok(self.queued.pop_front().map(Event::Key));
+        };
+        let start = Instant::now();
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            match events.next() {
+                Some(Err(error)) if is_transient(&error) => (),
+                Some(event) => return event.map(Some),
+                None => (),
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
             }
+            thread::sleep(POLL_INTERVAL);
         }
     }
 
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn cursor_hide(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", termion::cursor::Hide)
     }
 
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    pub fn cursor_show(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", termion::cursor::Show)
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    pub fn clear_current_line(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", termion::clear::CurrentLine)
+    }
+
+    pub fn set_fg_color(out: &mut dyn Write, color: color::Rgb) -> Result<(), std::io::Error> {
+        write!(out, "{}", color::Fg(color))
+    }
+    pub fn reset_fg_color(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", color::Fg(color::Reset))
     }
 
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    pub fn set_bg_color(out: &mut dyn Write, color: color::Rgb) -> Result<(), std::io::Error> {
+        write!(out, "{}", color::Bg(color))
     }
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+    pub fn reset_bg_color(out: &mut dyn Write) -> Result<(), std::io::Error> {
+        write!(out, "{}", color::Bg(color::Reset))
+    }
+
+    /// Puts `text` on the system clipboard with an OSC 52 escape sequence,
+    /// which terminals honor even over SSH. Returns `false` without writing
+    /// anything when `text` is too long to be accepted.
+    pub fn set_clipboard(out: &mut dyn Write, text: &str) -> Result<bool, std::io::Error> {
+        let encoded = base64(text.as_bytes());
+        if encoded.len() > CLIPBOARD_LIMIT {
+            return Ok(false);
+        }
+        write!(out, "\x1b]52;c;{encoded}\x07")?;
+        Self::flush(out)?;
+        Ok(true)
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |group, (index, byte)| {
+                group | u32::from(*byte) << (16 - 8 * index)
+            });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(BASE64_ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
     }
+    encoded
+}
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+/// The text left on a screen of `width` by `height` cells once `output`
+/// is drawn on it, each line without its trailing spaces. Only the escape
+/// sequences the editor draws with are understood: moving the cursor and
+/// clearing, with colors and the rest ignored.
+pub fn screen_text(output: &str, width: usize, height: usize) -> String {
+    let blank = || Some(" ".to_string());
+    let mut screen = vec![vec![blank(); width]; height];
+    let (mut line, mut column) = (0, 0);
+    let mut chars = output.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                let mut parameters = String::new();
+                let Some(command) = chars.find(|c| match c {
+                    '\x40'..='\x7e' => true,
+                    c => {
+                        parameters.push(*c);
+                        false
+                    }
+                }) else {
+                    break;
+                };
+                match command {
+                    'H' => {
+                        let mut numbers = parameters
+                            .split(';')
+                            .map(|number| number.parse::<usize>().unwrap_or(1).saturating_sub(1));
+                        line = numbers.next().unwrap_or(0);
+                        column = numbers.next().unwrap_or(0);
+                    }
+                    'K' => {
+                        if let Some(cells) = screen.get_mut(line) {
+                            cells.fill(blank());
+                        }
+                    }
+                    'J' => screen.iter_mut().for_each(|cells| cells.fill(blank())),
+                    _ => (),
+                }
+            }
+            // Operating system commands, like setting the clipboard
+            '\x1b' if chars.peek() == Some(&']') => {
+                chars.find(|c| *c == '\x07');
+            }
+            '\r' => column = 0,
+            '\n' => line += 1,
+            c => {
+                // Cells hold whole graphemes, so the text up to the next
+                // escape sequence or line break is split into them first
+                let mut text = c.to_string();
+                while let Some(c) = chars.next_if(|c| !matches!(c, '\x1b' | '\r' | '\n')) {
+                    text.push(c);
+                }
+                for grapheme in text.graphemes(true) {
+                    let cell_width = grapheme.width();
+                    if cell_width == 0 {
+                        continue;
+                    }
+                    if let Some(cells) = screen.get_mut(line) {
+                        if column + cell_width <= width {
+                            cells[column] = Some(grapheme.to_string());
+                            // The cells a wide grapheme covers hold nothing
+                            for cell in &mut cells[column + 1..column + cell_width] {
+                                *cell = None;
+                            }
+                        }
+                    }
+                    column += cell_width;
+                }
+            }
+        }
     }
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    screen
+        .into_iter()
+        .map(|cells| {
+            let text: String = cells.into_iter().flatten().collect();
+            text.trim_end().to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Whether a failed read is worth trying again, like one interrupted by a
+/// signal such as the one telling about a resize
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Puts the terminal back the way the editor found it, whether it quits
+/// normally or unwinds from a panic. Raw mode and mouse reporting are turned
+/// off when `_stdout` is dropped right after.
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.is_headless() {
+            return;
+        }
+        let out = &mut io::stdout();
+        write!(out, "\x1b[?2004l")
+            .and_then(|()| Self::reset_fg_color(out))
+            .and_then(|()| Self::reset_bg_color(out))
+            .and_then(|()| Self::cursor_show(out))
+            .and_then(|()| Self::flush(out))
+            .ok();
     }
 }
@@ -0,0 +1,148 @@
+//! Golden tests of what the editor shows, comparing frames drawn by a
+//! headless `Editor` with the ones kept in `tests/golden`. Running them
+//! with `UPDATE_GOLDEN=1` writes the current frames there instead.
+#![warn(clippy::all, clippy::pedantic)]
+
+use std::{env, fs, path::Path, sync::Once};
+
+use editore::{load_syntaxes, load_themes, Arguments, Config, Editor, HighLightManager};
+use termion::event::Key;
+
+const WIDTH: u16 = 60;
+const HEIGHT: u16 = 10;
+
+static STATE_HOME: Once = Once::new();
+
+/// Opens `fixture` from `tests/fixtures`, feeds it `keys` and checks the
+/// frame drawn then against `tests/golden/<name>.txt`
+fn assert_frame(name: &str, fixture: &str, keys: &[Key]) {
+    // Keep the files the editor remembers between runs out of the user's
+    // state directory, setting it before any test reads it
+    STATE_HOME.call_once(|| env::set_var("XDG_STATE_HOME", env!("CARGO_TARGET_TMPDIR")));
+    let file_name = format!("tests/fixtures/{fixture}");
+    let arguments = Arguments {
+        read_only: false,
+        files: vec![(file_name.clone(), None)],
+    };
+    let (ps, _) = load_syntaxes();
+    let (ts, _) = load_themes();
+    let mut h = HighLightManager::default(&ps, &ts, Some(&file_name));
+    let mut editor = Editor::headless(&arguments, Config::default(), WIDTH, HEIGHT);
+    editor.feed_keys(&mut h, keys).unwrap();
+    let frame = editor.snapshot(&mut h).unwrap();
+
+    let golden = Path::new("tests/golden").join(format!("{name}.txt"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &frame).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|error| panic!("Could not read {}: {error}", golden.display()));
+    assert_eq!(frame, expected, "frame differs from {}", golden.display());
+}
+
+fn typed(text: &str) -> Vec<Key> {
+    text.chars().map(Key::Char).collect()
+}
+
+#[test]
+fn opens_a_file() {
+    assert_frame("opens_a_file", "hello.rs", &[]);
+}
+
+#[test]
+fn types_into_the_document() {
+    let mut keys = vec![Key::Down, Key::End];
+    keys.extend(typed(" // said once"));
+    assert_frame("types_into_the_document", "hello.rs", &keys);
+}
+
+#[test]
+fn moves_over_wide_and_combining_characters() {
+    let mut keys = vec![Key::Down, Key::Down];
+    keys.extend([Key::Right; 14]);
+    keys.extend(typed("|"));
+    assert_frame("wide_characters", "notes.md", &keys);
+}
+
+#[test]
+fn cuts_and_undoes_a_line() {
+    assert_frame("cut_line", "hello.rs", &[Key::Down, Key::Ctrl('k')]);
+    assert_frame(
+        "cut_line_undone",
+        "hello.rs",
+        &[Key::Down, Key::Ctrl('k'), Key::Ctrl('z')],
+    );
+}
+
+#[test]
+fn searches_for_a_word() {
+    let mut keys = vec![Key::Ctrl('f')];
+    keys.extend(typed("greeting"));
+    keys.push(Key::Char('\n'));
+    assert_frame("search", "hello.rs", &keys);
+}
+
+#[test]
+fn shows_the_help() {
+    assert_frame("help", "hello.rs", &[Key::Ctrl('h')]);
+}
+
+#[test]
+fn stays_in_an_empty_document() {
+    assert_frame(
+        "empty_document",
+        "empty.txt",
+        &[
+            Key::Down,
+            Key::Right,
+            Key::End,
+            Key::PageDown,
+            Key::Delete,
+            Key::Backspace,
+        ],
+    );
+}
+
+#[test]
+fn stops_at_the_end_of_the_last_row() {
+    assert_frame(
+        "last_row",
+        "hello.rs",
+        &[Key::PageDown, Key::Down, Key::End, Key::Right, Key::Delete],
+    );
+}
+
+#[test]
+fn truncates_a_file_name_before_an_emoji_at_the_edge() {
+    assert_frame("wide_file_name", "ab日👍🏽本.txt", &[]);
+}
+
+#[test]
+fn stopping_a_replace_puts_the_cursor_back() {
+    let mut keys = vec![Key::Ctrl('r')];
+    keys.extend(typed("greeting\nname\ny"));
+    keys.push(Key::Esc);
+    assert_frame("replace_stopped", "hello.rs", &keys);
+}
+
+#[test]
+fn continues_a_list_until_enter_is_pressed_twice() {
+    let mut keys = vec![Key::End];
+    keys.extend(typed("\ntwo\n\nafter"));
+    assert_frame("list_continued", "list.md", &keys);
+}
+
+#[test]
+fn leaves_a_comment_marker_typed_by_hand() {
+    let mut keys = vec![Key::Down, Key::End];
+    keys.extend(typed("\n//\nx"));
+    assert_frame("comment_marker_kept", "hello.rs", &keys);
+}
+
+#[test]
+fn viewing_a_file_that_cannot_be_opened_leaves_the_buffer_editable() {
+    let mut keys = vec![Key::Ctrl('e')];
+    keys.extend(typed("view tests/fixtures/hello.rs/missing\nz"));
+    assert_frame("view_failed", "hello.rs", &keys);
+}